@@ -0,0 +1,107 @@
+//! compare counting a response by the old global maps and by per-worker shards,
+//! routes and hosts are counted in shards by their slots, without lock, run with `cargo bench --bench stats`
+#[macro_use]
+extern crate lazy_static;
+
+use dashmap::DashMap;
+use hyper::Method;
+use std::sync::RwLock;
+use test_server::stats::{Slot, Stats};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const THREADS: usize = 8;
+const OPERATIONS: usize = 1_000_000;
+const ROUTES: [&str; 4] = ["/", "/api/users", "/api/users/{id}", "unmatched"];
+const STATUS_CODES: [u16; 4] = [200, 201, 404, 500];
+
+lazy_static! {
+    // the statistics counted before shards
+    static ref STATISTICS: DashMap<usize, DashMap<u16, Box<u64>>> = DashMap::new();
+    static ref TOTAL_CONNECTIONS: RwLock<u64> = RwLock::new(0);
+    static ref ROUTE_STATISTICS: DashMap<(Method, String, u16), u64> = DashMap::new();
+    static ref HOST_STATISTICS: DashMap<String, u64> = DashMap::new();
+    static ref STATS: Stats = Stats::new();
+    // kept by routes and hosts of a config in the server
+    static ref ROUTE_SLOTS: Vec<Slot> = ROUTES.iter().map(|_| Slot::default()).collect();
+    static ref HOST_SLOT: usize = STATS.host_slot("default");
+}
+
+fn old_inc_response(thread_id: usize, method: &Method, route: &str, status_code: u16) {
+    *ROUTE_STATISTICS.entry((method.clone(), route.to_string(), status_code)).or_insert(0) += 1;
+    match STATISTICS.get(&thread_id) {
+        Some(codes) => match codes.get_mut(&status_code) {
+            Some(mut count) => **count += 1,
+            None => {
+                codes.insert(status_code, Box::new(1));
+            }
+        },
+        None => {
+            let codes = DashMap::new();
+            codes.insert(status_code, Box::new(1));
+            STATISTICS.insert(thread_id, codes);
+        }
+    }
+}
+
+fn old_inc_host_request(host: &str) {
+    match HOST_STATISTICS.get_mut(host) {
+        Some(mut count) => *count += 1,
+        None => {
+            HOST_STATISTICS.insert(host.to_string(), 1);
+        }
+    }
+}
+
+fn old_inc_connections() {
+    *TOTAL_CONNECTIONS.write().unwrap() += 1;
+}
+
+fn new_inc(method: &Method, index: usize, status_code: u16) {
+    STATS.inc_connections();
+    STATS.inc_host_request("default", *HOST_SLOT);
+    STATS.inc_response(method, ROUTES[index], &ROUTE_SLOTS[index], status_code);
+}
+
+fn old_inc(thread_id: usize, method: &Method, route: &str, status_code: u16) {
+    old_inc_connections();
+    old_inc_host_request("default");
+    old_inc_response(thread_id, method, route, status_code);
+}
+
+// time of every operation, counted by all threads at the same time
+fn run<F: Fn(usize, usize) + Send + Sync + Copy + 'static>(count: F) -> Duration {
+    let start = Instant::now();
+    let threads: Vec<_> = (0..THREADS)
+        .map(|thread_id| {
+            thread::spawn(move || {
+                for operation in 0..OPERATIONS {
+                    count(thread_id, operation);
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    start.elapsed() / (THREADS * OPERATIONS) as u32
+}
+
+fn main() {
+    let old = run(|thread_id, operation| {
+        let index = operation % ROUTES.len();
+        old_inc(thread_id, &Method::GET, ROUTES[index], STATUS_CODES[index]);
+    });
+    let new = run(|_, operation| {
+        let index = operation % ROUTES.len();
+        new_inc(&Method::GET, index, STATUS_CODES[index]);
+    });
+
+    let total: u64 = STATS.response_statistic().values().sum();
+    assert_eq!(total, (THREADS * OPERATIONS) as u64);
+    let total: u64 = STATS.route_statistic().iter().map(|(_, _, _, count)| count).sum();
+    assert_eq!(total, (THREADS * OPERATIONS) as u64);
+    println!("{} threads x {} responses", THREADS, OPERATIONS);
+    println!("global maps: {:?}/response", old);
+    println!("shards:      {:?}/response", new);
+}
@@ -0,0 +1,307 @@
+use chrono::{DateTime, Local, SecondsFormat};
+use hyper::header::{HeaderMap, HeaderName, REFERER, USER_AGENT};
+use hyper::{Method, Version};
+use serde_json::json;
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread::{self, JoinHandle};
+
+// variables a template may use, `$http_<name>` is the request header of name with `_` as `-`
+const VARIABLES: [&str; 18] = [
+    "remote_addr",
+    "remote_port",
+    "realip_remote_addr",
+    "realip_remote_port",
+    "time_local",
+    "time_iso8601",
+    "request",
+    "request_method",
+    "request_uri",
+    "uri",
+    "args",
+    "server_protocol",
+    "status",
+    "body_bytes_sent",
+    "request_time",
+    "request_time_us",
+    "route",
+    "host",
+];
+const HEADER_VARIABLE_PREFIX: &str = "http_";
+
+/// format of access log lines
+pub enum LogFormat {
+    // nginx combined format
+    Combined,
+    // one json object per line
+    Json,
+    // text with `$variable` like nginx log_format
+    Template(Vec<Segment>),
+}
+
+pub enum Segment {
+    Text(String),
+    Variable(String),
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "combined" => return Ok(LogFormat::Combined),
+            "json" => return Ok(LogFormat::Json),
+            _ => {}
+        }
+        if !s.contains('$') {
+            return Err(format!("unknown access log format {}, combined, json or a template with $variable is expected", s));
+        }
+        let mut segments = Vec::new();
+        let mut rest = s;
+        while let Some(start) = rest.find('$') {
+            if start > 0 {
+                segments.push(Segment::Text(rest[..start].to_string()));
+            }
+            let name_len = rest[start + 1..].find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len() - start - 1);
+            let name = &rest[start + 1..start + 1 + name_len];
+            let header = name.starts_with(HEADER_VARIABLE_PREFIX) && name.len() > HEADER_VARIABLE_PREFIX.len();
+            if !header && !VARIABLES.contains(&name) {
+                return Err(format!("unknown access log variable ${}", name));
+            }
+            segments.push(Segment::Variable(name.to_string()));
+            rest = &rest[start + 1 + name_len..];
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Text(rest.to_string()));
+        }
+        Ok(LogFormat::Template(segments))
+    }
+}
+
+/// a request and its response, logged when response body is sent
+pub struct AccessRecord {
+    pub time: DateTime<Local>,
+    // source in proxy protocol header if there is one
+    pub client: Option<SocketAddr>,
+    // tcp peer, the proxy if there is one
+    pub peer: Option<SocketAddr>,
+    pub method: Method,
+    // path and query
+    pub uri: String,
+    pub version: Version,
+    pub headers: HeaderMap,
+    // virtual host answering the request
+    pub host: String,
+    // url pattern of the matched route, or unmatched
+    pub route: String,
+    pub status: u16,
+    pub body_bytes_sent: u64,
+    // microseconds from request head to response completion
+    pub request_time: u64,
+}
+
+impl AccessRecord {
+    fn variable(&self, name: &str) -> String {
+        match name {
+            "remote_addr" => self.client.map(|client| client.ip().to_string()).unwrap_or_else(|| "-".to_string()),
+            "remote_port" => self.client.map(|client| client.port().to_string()).unwrap_or_else(|| "-".to_string()),
+            "realip_remote_addr" => self.peer.map(|peer| peer.ip().to_string()).unwrap_or_else(|| "-".to_string()),
+            "realip_remote_port" => self.peer.map(|peer| peer.port().to_string()).unwrap_or_else(|| "-".to_string()),
+            "time_local" => self.time.format("%d/%b/%Y:%H:%M:%S %z").to_string(),
+            "time_iso8601" => self.time.to_rfc3339_opts(SecondsFormat::Secs, false),
+            "request" => format!("{} {} {}", self.method, self.uri, protocol(self.version)),
+            "request_method" => self.method.to_string(),
+            "request_uri" => self.uri.clone(),
+            "uri" => self.uri.split('?').next().unwrap_or("").to_string(),
+            "args" => self.uri.split_once('?').map(|(_, args)| args).unwrap_or("").to_string(),
+            "server_protocol" => protocol(self.version).to_string(),
+            "status" => self.status.to_string(),
+            "body_bytes_sent" => self.body_bytes_sent.to_string(),
+            // seconds with milliseconds like nginx
+            "request_time" => format!("{:.3}", self.request_time as f64 / 1_000_000.0),
+            "request_time_us" => self.request_time.to_string(),
+            "route" => self.route.clone(),
+            "host" => self.host.clone(),
+            _ => {
+                let header = name[HEADER_VARIABLE_PREFIX.len()..].replace('_', "-");
+                match HeaderName::from_bytes(header.as_bytes()) {
+                    Ok(header) => self.header(&header),
+                    Err(_) => "-".to_string(),
+                }
+            }
+        }
+    }
+
+    // value of request header, `-` if missing
+    fn header(&self, name: &HeaderName) -> String {
+        self.headers
+            .get(name)
+            .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+            .unwrap_or_else(|| "-".to_string())
+    }
+
+    fn to_combined(&self) -> String {
+        format!(
+            "{} - - [{}] \"{}\" {} {} \"{}\" \"{}\"",
+            self.variable("remote_addr"),
+            self.variable("time_local"),
+            escape(&self.variable("request")),
+            self.status,
+            self.body_bytes_sent,
+            escape(&self.header(&REFERER)),
+            escape(&self.header(&USER_AGENT))
+        )
+    }
+
+    fn to_json(&self) -> String {
+        json!({
+            "time": self.variable("time_iso8601"),
+            "remote_addr": self.client.map(|client| client.ip().to_string()),
+            "realip_remote_addr": self.peer.map(|peer| peer.ip().to_string()),
+            "method": self.method.as_str(),
+            "uri": self.uri,
+            "protocol": protocol(self.version),
+            "host": self.host,
+            "route": self.route,
+            "status": self.status,
+            "body_bytes_sent": self.body_bytes_sent,
+            "request_time_us": self.request_time,
+            "user_agent": self.headers.get(USER_AGENT).map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned()),
+            "referer": self.headers.get(REFERER).map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned()),
+        })
+        .to_string()
+    }
+
+    fn to_template(&self, segments: &[Segment]) -> String {
+        let mut line = String::new();
+        for segment in segments.iter() {
+            match segment {
+                Segment::Text(text) => line.push_str(text),
+                Segment::Variable(name) => line.push_str(&escape(&self.variable(name))),
+            }
+        }
+        line
+    }
+}
+
+fn protocol(version: Version) -> &'static str {
+    match version {
+        Version::HTTP_09 => "HTTP/0.9",
+        Version::HTTP_10 => "HTTP/1.0",
+        Version::HTTP_11 => "HTTP/1.1",
+        Version::HTTP_2 => "HTTP/2.0",
+        _ => "HTTP/3.0",
+    }
+}
+
+// double quote, backslash and bytes not printable are escaped as \xHH like nginx, so a line is never broken
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if byte == b'"' || byte == b'\\' || !(0x20..0x7f).contains(&byte) {
+            write!(escaped, "\\x{:02X}", byte).unwrap();
+        } else {
+            escaped.push(byte as char);
+        }
+    }
+    escaped
+}
+
+/// access log file, reopened with the same path after it's rotated,
+/// lines are written by a thread of its own, so requests never wait for the disk
+pub struct AccessLog {
+    path: String,
+    sender: Sender<Message>,
+    writer: Option<JoinHandle<()>>,
+}
+
+enum Message {
+    Record(Box<AccessRecord>),
+    // file opened again after rotation
+    Reopen(File),
+    Close,
+}
+
+fn open(path: &str) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+impl AccessLog {
+    /// file is created if it does not exist, lines are appended to it
+    pub fn open(path: &str, format: LogFormat) -> io::Result<Self> {
+        let file = open(path)?;
+        let (sender, receiver) = mpsc::channel();
+        let writer = thread::Builder::new().name("access-log".to_string()).spawn(move || write_lines(file, format, receiver))?;
+        Ok(AccessLog { path: path.to_string(), sender, writer: Some(writer) })
+    }
+
+    /// open file of the path again, for logrotate which moved it away
+    pub fn reopen(&self) -> io::Result<()> {
+        let file = open(&self.path)?;
+        // lines before it are written to the old file
+        let _ = self.sender.send(Message::Reopen(file));
+        Ok(())
+    }
+
+    /// append a line of record, it's formatted and written by the writer thread
+    pub fn write(&self, record: AccessRecord) {
+        let _ = self.sender.send(Message::Record(Box::new(record)));
+    }
+}
+
+impl Drop for AccessLog {
+    // lines sent already are written before it's closed
+    fn drop(&mut self) {
+        let _ = self.sender.send(Message::Close);
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+// lines received together are written together, they are flushed once no more is waiting
+fn write_lines(file: File, format: LogFormat, receiver: Receiver<Message>) {
+    let mut file = BufWriter::new(file);
+    loop {
+        let message = match receiver.try_recv() {
+            Ok(message) => message,
+            Err(TryRecvError::Empty) => {
+                if let Err(e) = file.flush() {
+                    println!("write access log failed: {}", e);
+                }
+                match receiver.recv() {
+                    Ok(message) => message,
+                    Err(_) => return,
+                }
+            }
+            Err(TryRecvError::Disconnected) => break,
+        };
+        match message {
+            Message::Record(record) => {
+                let mut line = match &format {
+                    LogFormat::Combined => record.to_combined(),
+                    LogFormat::Json => record.to_json(),
+                    LogFormat::Template(segments) => record.to_template(segments),
+                };
+                line.push('\n');
+                if let Err(e) = file.write_all(line.as_bytes()) {
+                    println!("write access log failed: {}", e);
+                }
+            }
+            Message::Reopen(reopened) => {
+                if let Err(e) = file.flush() {
+                    println!("write access log failed: {}", e);
+                }
+                file = BufWriter::new(reopened);
+            }
+            Message::Close => break,
+        }
+    }
+    if let Err(e) = file.flush() {
+        println!("write access log failed: {}", e);
+    }
+}
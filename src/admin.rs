@@ -0,0 +1,291 @@
+use crate::journal::JournalEntry;
+use crate::loader::build_route_info;
+use crate::metrics::{self, MetricsWriter};
+use crate::server::ServerState;
+use crate::types::config::RouteConfig;
+use crate::types::diagnostic::Diagnostics;
+use crate::types::route::Content;
+use crate::types::spec::{FilterSpec, RouteSpec, DEFAULT_HOST};
+use hyper::header::CONTENT_TYPE;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use itertools::Itertools;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::str::FromStr;
+use std::sync::Arc;
+use yaml_rust::{Yaml, YamlLoader};
+
+/// answer requests to admin listener
+pub(crate) async fn admin_response(state: Arc<ServerState>, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let query: HashMap<String, String> = form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes()).into_owned().collect();
+    match (req.method(), req.uri().path()) {
+        (&Method::GET, "/metrics") => {
+            // netstat reads files of proc, keep it off async threads
+            let metrics = tokio::task::spawn_blocking(move || render_metrics(&state)).await.unwrap_or_default();
+            Ok(Response::builder().header(CONTENT_TYPE, metrics::CONTENT_TYPE).body(Body::from(metrics)).unwrap())
+        }
+        (&Method::GET, "/routes") => {
+            let routes = tokio::task::spawn_blocking(move || list_routes(&state.route_config())).await.unwrap_or_default();
+            Ok(Response::builder().header(CONTENT_TYPE, "application/json").body(Body::from(routes)).unwrap())
+        }
+        (&Method::PUT, "/routes") => {
+            let body = match hyper::body::to_bytes(req.into_body()).await {
+                Ok(body) => body,
+                Err(e) => return Ok(admin_error(StatusCode::BAD_REQUEST, format!("read request body failed: {}\n", e))),
+            };
+            // requests do not lock routes while they are answered, so routes are changed at once
+            Ok(put_route(&state, &query, &body))
+        }
+        (&Method::DELETE, "/routes") => Ok(delete_route(&state.route_config(), &query)),
+        (_, "/requests") | (_, "/requests/find") | (_, "/requests/count") if state.journal.is_none() => {
+            Ok(admin_error(StatusCode::NOT_FOUND, "journal is not enabled, start with --journal\n".to_string()))
+        }
+        (&Method::GET, "/requests") => {
+            let limit = query.get("limit").and_then(|limit| limit.parse().ok()).unwrap_or(usize::MAX);
+            let entries = state.requests(&FilterSpec::default());
+            let entries = entries.iter().skip(entries.len().saturating_sub(limit));
+            let entries: Vec<_> = entries.map(|(id, entry)| entry.to_json(*id)).collect();
+            Ok(Response::builder().header(CONTENT_TYPE, "application/json").body(Body::from(serde_json::Value::from(entries).to_string())).unwrap())
+        }
+        (&Method::POST, "/requests/find") | (&Method::POST, "/requests/count") => {
+            let count = req.uri().path() == "/requests/count";
+            let body = match hyper::body::to_bytes(req.into_body()).await {
+                Ok(body) => body,
+                Err(e) => return Ok(admin_error(StatusCode::BAD_REQUEST, format!("read request body failed: {}\n", e))),
+            };
+            // regex and json path matchers may be slow
+            let entries = match tokio::task::spawn_blocking(move || find_requests(&state, &body)).await {
+                Ok(Ok(entries)) => entries,
+                Ok(Err(e)) => return Ok(admin_error(StatusCode::BAD_REQUEST, e)),
+                Err(e) => return Ok(admin_error(StatusCode::INTERNAL_SERVER_ERROR, format!("find requests failed: {}\n", e))),
+            };
+            let result = if count {
+                serde_json::json!({ "count": entries.len() })
+            } else {
+                entries.iter().map(|(id, entry)| entry.to_json(*id)).collect::<Vec<_>>().into()
+            };
+            Ok(Response::builder().header(CONTENT_TYPE, "application/json").body(Body::from(result.to_string())).unwrap())
+        }
+        (&Method::DELETE, "/requests") => {
+            if let Some(journal) = &state.journal {
+                journal.clear();
+            }
+            Ok(Response::builder().status(StatusCode::NO_CONTENT).body(Body::empty()).unwrap())
+        }
+        (&Method::POST, "/stats/reset") => {
+            state.stats.reset();
+            Ok(Response::builder().status(StatusCode::NO_CONTENT).body(Body::empty()).unwrap())
+        }
+        _ => Ok(Response::builder().status(StatusCode::NOT_FOUND).body(Body::empty()).unwrap()),
+    }
+}
+
+fn admin_error(status: StatusCode, message: String) -> Response<Body> {
+    Response::builder().status(status).body(Body::from(message)).unwrap()
+}
+
+// host and method of a route in admin api query, host is the default host if not given
+fn admin_route_key(query: &HashMap<String, String>) -> Result<(String, Method), String> {
+    let host = query.get("host").map(|host| host.to_lowercase()).unwrap_or_else(|| DEFAULT_HOST.to_string());
+    let method = match query.get("method").map(|method| Method::from_str(&method.to_uppercase())) {
+        Some(Ok(method)) => method,
+        Some(Err(e)) => return Err(format!("method error: {}\n", e)),
+        None => return Err("method is required\n".to_string()),
+    };
+    Ok((host, method))
+}
+
+/// routes of current configuration in json, conditional routes of same url and method are in the order they are matched
+fn list_routes(config: &RouteConfig) -> String {
+    let mut routes = Vec::new();
+    for entry in config.routes.iter() {
+        let (host, url) = entry.key();
+        for (method, method_routes) in entry.value().iter() {
+            for route in method_routes.iter() {
+                let headers: serde_json::Map<String, serde_json::Value> = route
+                    .headers
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), String::from_utf8_lossy(value.as_bytes()).into()))
+                    .chain(route.header_templates.iter().map(|(name, template)| (name.to_string(), template.source.clone().into())))
+                    .collect();
+                let mut item = serde_json::json!({
+                    "host": host,
+                    "method": method.as_str(),
+                    "url": url,
+                    "status_code": route.status_code.as_u16(),
+                    "headers": headers,
+                    "conditional": !(route.query.is_empty() && route.match_headers.is_empty() && route.match_body.is_empty()),
+                });
+                match &route.body {
+                    Content::Content(body) => item["body"] = body.clone().into(),
+                    Content::Cache(file) | Content::File(file) => item["file"] = file.clone().into(),
+                    Content::Echo => item["type"] = "echo".into(),
+                    Content::Template(template, file) => {
+                        match file {
+                            Some(file) => item["file"] = file.clone().into(),
+                            None => item["body"] = template.source.clone().into(),
+                        }
+                        item["template"] = true.into();
+                    }
+                }
+                routes.push(item);
+            }
+        }
+    }
+    routes.sort_by(|a, b| (a["host"].as_str(), a["url"].as_str(), a["method"].as_str()).cmp(&(b["host"].as_str(), b["url"].as_str(), b["method"].as_str())));
+    serde_json::Value::from(routes).to_string()
+}
+
+/// replace routes of url, method and host by the route in body, which has the keys of a route in yaml, json is yaml too
+fn put_route(state: &ServerState, query: &HashMap<String, String>, body: &[u8]) -> Response<Body> {
+    let (host, method) = match admin_route_key(query) {
+        Ok(key) => key,
+        Err(e) => return admin_error(StatusCode::BAD_REQUEST, e),
+    };
+    let source = match std::str::from_utf8(body) {
+        Ok(source) => source,
+        Err(e) => return admin_error(StatusCode::BAD_REQUEST, format!("body should be utf-8: {}\n", e)),
+    };
+    let yaml = match YamlLoader::load_from_str(source) {
+        Ok(docs) => docs.into_iter().next().unwrap_or(Yaml::BadValue),
+        Err(e) => return admin_error(StatusCode::BAD_REQUEST, format!("parse body failed: {}\n", e)),
+    };
+
+    let mut diagnostics = Diagnostics::new("body", source);
+    let route = RouteSpec::from_yaml(&yaml, &host, method.clone(), &mut diagnostics);
+    let config = state.route_config();
+    let route = route.filter(|_| state.is_config_accepted(&diagnostics)).map(|route| {
+        let pattern = route.pattern.clone();
+        (pattern, build_route_info(&config.file_cache, route, &mut diagnostics))
+    });
+    let (pattern, route) = match route {
+        Some(route) => route,
+        None => return admin_error(StatusCode::BAD_REQUEST, diagnostics.to_string()),
+    };
+
+    println!("put url: {} {}{}", method, host, route.url);
+    if host != DEFAULT_HOST {
+        config.hosts.entry(host.clone()).or_default().get_or_give(|| state.stats.host_slot(&host));
+    }
+    if let Some(pattern) = pattern {
+        let mut patterns = config.patterns.entry(host.clone()).or_default();
+        if !patterns.iter().any(|element| element.pattern == pattern.pattern) {
+            patterns.push(pattern);
+            patterns.sort_by(|a, b| a.precedence(b));
+        }
+    }
+    let replaced = config.routes.entry((host, route.url.clone())).or_default().insert(method, vec![Arc::new(route)]).is_some();
+    let status = if replaced { StatusCode::OK } else { StatusCode::CREATED };
+    // warnings of the route are told
+    Response::builder().status(status).body(Body::from(diagnostics.to_string())).unwrap()
+}
+
+/// recorded requests matching the filter in body, which has the matchers of a route in yaml,
+/// and method, host, route and status_code, empty body matches every request
+fn find_requests(state: &ServerState, body: &[u8]) -> Result<Vec<(u64, Arc<JournalEntry>)>, String> {
+    let source = std::str::from_utf8(body).map_err(|e| format!("body should be utf-8: {}\n", e))?;
+    let yaml = match YamlLoader::load_from_str(source) {
+        Ok(docs) => docs.into_iter().next().unwrap_or(Yaml::BadValue),
+        Err(e) => return Err(format!("parse body failed: {}\n", e)),
+    };
+    let mut diagnostics = Diagnostics::new("body", source);
+    // a typo in filter would match more requests than expected, so warnings are refused too
+    match FilterSpec::from_yaml(&yaml, &mut diagnostics) {
+        Some(filter) if diagnostics.is_empty() => Ok(state.requests(&filter)),
+        _ => Err(diagnostics.to_string()),
+    }
+}
+
+/// delete routes of url, method and host
+fn delete_route(config: &RouteConfig, query: &HashMap<String, String>) -> Response<Body> {
+    let (host, method) = match admin_route_key(query) {
+        Ok(key) => key,
+        Err(e) => return admin_error(StatusCode::BAD_REQUEST, e),
+    };
+    let url = match query.get("url") {
+        Some(url) => url.clone(),
+        None => return admin_error(StatusCode::BAD_REQUEST, "url is required\n".to_string()),
+    };
+
+    let key = (host.clone(), url.clone());
+    let deleted = match config.routes.get_mut(&key) {
+        Some(mut routes) => routes.remove(&method).is_some(),
+        None => false,
+    };
+    if !deleted {
+        return admin_error(StatusCode::NOT_FOUND, format!("no route: {} {}{}\n", method, host, url));
+    }
+    println!("delete url: {} {}{}", method, host, url);
+    // pattern is not needed when no method of the url is left
+    if config.routes.remove_if(&key, |_, routes| routes.is_empty()).is_some() {
+        if let Some(mut patterns) = config.patterns.get_mut(&host) {
+            patterns.retain(|pattern| pattern.pattern != url);
+        }
+    }
+    Response::builder().status(StatusCode::NO_CONTENT).body(Body::empty()).unwrap()
+}
+
+/// statistics in prometheus text format
+fn render_metrics(state: &ServerState) -> String {
+    let stats = &state.stats;
+    let mut writer = MetricsWriter::default();
+
+    writer.family("test_server_responses_total", "counter", "Responses by status code.");
+    for (code, count) in stats.response_statistic().iter().sorted() {
+        writer.sample("test_server_responses_total", &[("status", &code.to_string())], count);
+    }
+
+    writer.family(
+        "test_server_route_responses_total",
+        "counter",
+        "Responses by method, route and status code, route is the url pattern of matched route.",
+    );
+    for (method, route, code, count) in stats.route_statistic() {
+        let labels = [("method", method.as_str()), ("route", &route), ("status", &code.to_string())];
+        writer.sample("test_server_route_responses_total", &labels, count);
+    }
+
+    // top paths only, label values of all paths are unbounded
+    writer.family(
+        "test_server_unmatched_path_requests_total",
+        "counter",
+        "Requests of the most requested paths matching no route.",
+    );
+    for (path, count) in state.top_unmatched_paths() {
+        writer.sample("test_server_unmatched_path_requests_total", &[("path", &path)], count);
+    }
+
+    writer.family("test_server_version_requests_total", "counter", "Requests by http version.");
+    for (version, count) in stats.version_statistic() {
+        writer.sample("test_server_version_requests_total", &[("version", &format!("{:?}", version))], count);
+    }
+
+    writer.family("test_server_host_requests_total", "counter", "Requests by virtual host.");
+    for (host, count) in stats.host_statistic() {
+        writer.sample("test_server_host_requests_total", &[("host", &host)], count);
+    }
+
+    let (bytes_in, bytes_out) = stats.total_bytes();
+    writer.family("test_server_request_bytes_total", "counter", "Request body bytes received.");
+    writer.sample("test_server_request_bytes_total", &[], bytes_in);
+    writer.family("test_server_response_bytes_total", "counter", "Response body bytes sent.");
+    writer.sample("test_server_response_bytes_total", &[], bytes_out);
+
+    writer.family("test_server_connections_total", "counter", "Connections accepted from start.");
+    writer.sample("test_server_connections_total", &[], stats.total_connections());
+
+    writer.family(
+        "test_server_proxy_protocol_errors_total",
+        "counter",
+        "Connections closed for missing or malformed proxy protocol header.",
+    );
+    writer.sample("test_server_proxy_protocol_errors_total", &[], stats.proxy_protocol_errors());
+
+    let (connecting, closing, established) = state.connection_states();
+    writer.family("test_server_connections", "gauge", "Connections of listening ports by tcp state.");
+    for (state, count) in [("connecting", connecting), ("closing", closing), ("established", established)].iter() {
+        writer.sample("test_server_connections", &[("state", state)], count);
+    }
+
+    writer.finish()
+}
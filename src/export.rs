@@ -0,0 +1,156 @@
+use chrono::{DateTime, Local, SecondsFormat};
+use hyper::Method;
+use serde_json::{json, Map, Value};
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::str::FromStr;
+
+/// format of statistics records written on every refresh
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatsFormat {
+    // one json object per line
+    Json,
+    // one row per value, `timestamp,metric,label,value`, so new status codes and routes need no new column
+    Csv,
+}
+
+impl FromStr for StatsFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(StatsFormat::Json),
+            "csv" => Ok(StatsFormat::Csv),
+            _ => Err(format!("unknown statistics format {}, json or csv is expected", s)),
+        }
+    }
+}
+
+/// statistics of one refresh, counts are from start and rates are of the interval
+pub struct StatsRecord {
+    pub time: DateTime<Local>,
+    pub connections: u64,
+    // connections closed for missing or malformed proxy protocol header
+    pub proxy_protocol_errors: u64,
+    pub connecting: usize,
+    pub closing: usize,
+    pub established: usize,
+    pub requests_per_second: f64,
+    pub bytes_in_per_second: f64,
+    pub bytes_out_per_second: f64,
+    // name => microseconds, like p50
+    pub latency: Vec<(&'static str, u64)>,
+    // http version => requests from start
+    pub versions: Vec<(String, u64)>,
+    // sorted by status code
+    pub statuses: Vec<(u16, u64)>,
+    pub hosts: Vec<(String, u64)>,
+    pub routes: Vec<(Method, String, u16, u64)>,
+    pub unmatched_paths: Vec<(String, u64)>,
+}
+
+/// append statistics records to a file
+pub struct StatsExporter {
+    file: File,
+    format: StatsFormat,
+}
+
+impl StatsExporter {
+    /// file is created if it does not exist, csv header is written only to an empty file
+    pub fn open(path: &str, format: StatsFormat) -> io::Result<Self> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        if format == StatsFormat::Csv && file.metadata()?.len() == 0 {
+            file.write_all(b"timestamp,metric,label,value\n")?;
+        }
+        Ok(StatsExporter { file, format })
+    }
+
+    /// a record is written by one call, so a reader never sees half of it
+    pub fn write(&mut self, record: &StatsRecord) -> io::Result<()> {
+        let buffer = match self.format {
+            StatsFormat::Json => format!("{}\n", to_json(record)),
+            StatsFormat::Csv => to_csv(record),
+        };
+        self.file.write_all(buffer.as_bytes())
+    }
+}
+
+fn timestamp(record: &StatsRecord) -> String {
+    record.time.to_rfc3339_opts(SecondsFormat::Millis, false)
+}
+
+fn to_json(record: &StatsRecord) -> Value {
+    let latency: Map<String, Value> = record.latency.iter().map(|(name, micros)| (name.to_string(), json!(micros))).collect();
+    let versions: Map<String, Value> = record.versions.iter().map(|(version, count)| (version.clone(), json!(count))).collect();
+    let statuses: Map<String, Value> = record.statuses.iter().map(|(code, count)| (code.to_string(), json!(count))).collect();
+    let hosts: Map<String, Value> = record.hosts.iter().map(|(host, count)| (host.clone(), json!(count))).collect();
+    let routes: Vec<Value> = record
+        .routes
+        .iter()
+        .map(|(method, route, code, count)| json!({"method": method.as_str(), "route": route, "status": code, "count": count}))
+        .collect();
+    let unmatched_paths: Vec<Value> = record.unmatched_paths.iter().map(|(path, count)| json!({"path": path, "count": count})).collect();
+    json!({
+        "timestamp": timestamp(record),
+        "connections_total": record.connections,
+        "proxy_protocol_errors_total": record.proxy_protocol_errors,
+        "connecting": record.connecting,
+        "closing": record.closing,
+        "established": record.established,
+        "requests_per_second": record.requests_per_second,
+        "bytes_in_per_second": record.bytes_in_per_second,
+        "bytes_out_per_second": record.bytes_out_per_second,
+        "latency_us": latency,
+        "versions": versions,
+        "statuses": statuses,
+        "hosts": hosts,
+        "routes": routes,
+        "unmatched_paths": unmatched_paths,
+    })
+}
+
+fn to_csv(record: &StatsRecord) -> String {
+    let time = timestamp(record);
+    let mut buffer = String::new();
+    let mut row = |metric: &str, label: &str, value: &dyn std::fmt::Display| {
+        writeln!(buffer, "{},{},{},{}", time, metric, escape(label), value).unwrap();
+    };
+    row("connections_total", "", &record.connections);
+    row("proxy_protocol_errors_total", "", &record.proxy_protocol_errors);
+    row("connecting", "", &record.connecting);
+    row("closing", "", &record.closing);
+    row("established", "", &record.established);
+    row("requests_per_second", "", &format!("{:.1}", record.requests_per_second));
+    row("bytes_in_per_second", "", &format!("{:.1}", record.bytes_in_per_second));
+    row("bytes_out_per_second", "", &format!("{:.1}", record.bytes_out_per_second));
+    for (name, micros) in record.latency.iter() {
+        row("latency_us", name, micros);
+    }
+    for (version, count) in record.versions.iter() {
+        row("version", version, count);
+    }
+    for (code, count) in record.statuses.iter() {
+        row("status", &code.to_string(), count);
+    }
+    for (host, count) in record.hosts.iter() {
+        row("host", host, count);
+    }
+    // route label is `method route status`, route has no space
+    for (method, route, code, count) in record.routes.iter() {
+        row("route", &format!("{} {} {}", method, route, code), count);
+    }
+    for (path, count) in record.unmatched_paths.iter() {
+        row("unmatched_path", path, count);
+    }
+    buffer
+}
+
+// field with comma, double quote or line break is quoted
+fn escape(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
@@ -0,0 +1,510 @@
+use crate::access_log::AccessRecord;
+use crate::journal::{JournalEntry, BODY_LIMIT};
+use crate::server::ServerState;
+use crate::stats::{MeteredBody, Slot, Stats};
+use crate::tls::{ServerName, TlsInfo};
+use crate::types::client::{ClientAddr, PeerAddr};
+use crate::types::config::{MethodRoutes, RouteConfig};
+use crate::types::delay::Delay;
+use crate::types::pattern::{PathParams, RoutePattern};
+use crate::types::route::{Content, RequestParts, RouteInfo};
+use crate::types::template::TemplateContext;
+use crate::types::spec::DEFAULT_HOST;
+use chrono::Local;
+use hyper::body::{Bytes, HttpBody};
+use hyper::header::{HeaderValue, ALLOW, CONTENT_LENGTH, HOST};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use itertools::Itertools;
+use ring::digest;
+use serde_json::{json, Value};
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::io::AsyncReadExt;
+
+// route name of requests that match no route
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+// route name of requests answered by fallback
+const FALLBACK_ROUTE: &str = "fallback";
+
+// files not cached are sent to client by chunks of this size
+const FILE_CHUNK_SIZE: usize = 64 * 1024;
+
+/// result of route lookup, routes are not locked after it, so yaml reload and admin api never wait for requests
+enum RouteLookup {
+    // matched route and values captured from url
+    Found(Arc<RouteInfo>, PathParams),
+    // some route matches body, request body should be read before lookup again
+    NeedBody,
+    // url is matched, but not by request method, methods configured for it
+    MethodNotAllowed(Vec<Method>),
+    NotFound,
+}
+
+/// select virtual host by host header, then by tls server name, default host is used if none matches
+/// along with its slot in stats
+fn resolve_host(config: &RouteConfig, req: &Request<Body>) -> (String, usize) {
+    let hosts = &config.hosts;
+    let default_host = (DEFAULT_HOST.to_string(), config.default_host.get());
+    if hosts.is_empty() {
+        return default_host;
+    }
+
+    let host = req.headers().get(HOST).and_then(|host| host.to_str().ok()).or_else(|| req.uri().host());
+    if let Some(host) = host {
+        // remove port, ipv6 address is in brackets
+        let host = match host.find(']') {
+            Some(end) => &host[..=end],
+            None => host.split(':').next().unwrap_or(host),
+        };
+        let host = host.to_lowercase();
+        if let Some(slot) = hosts.get(&host) {
+            let slot = slot.get();
+            return (host, slot);
+        }
+    }
+
+    if let Some(ServerName(server_name)) = req.extensions().get::<ServerName>() {
+        if let Some(slot) = hosts.get(server_name) {
+            return (server_name.clone(), slot.get());
+        }
+    }
+    default_host
+}
+
+/// find route of virtual host by exact url first, then by patterns from the most specific one,
+/// the first route whose matchers all match is used
+fn lookup_route(config: &RouteConfig, host: &str, url: &str, method: &Method, request: &RequestParts) -> RouteLookup {
+    let mut allow = Vec::new();
+    // some url is matched by request method, but not by matchers
+    let mut method_matched = false;
+    // select route from routes of an url, error means request body is needed
+    let mut select = |routes: &MethodRoutes| -> Result<Option<Arc<RouteInfo>>, ()> {
+        match routes.get(method) {
+            Some(method_routes) => {
+                method_matched = true;
+                for route in method_routes.iter() {
+                    if route.matches(request).ok_or(())? {
+                        return Ok(Some(route.clone()));
+                    }
+                }
+                Ok(None)
+            }
+            None => {
+                allow.extend(routes.keys().cloned());
+                Ok(None)
+            }
+        }
+    };
+
+    // url of request may look like a pattern, it should be matched by patterns only
+    if let Ok(None) = RoutePattern::parse(url) {
+        if let Some(routes) = config.routes.get(&(host.to_string(), url.to_string())) {
+            match select(routes.value()) {
+                Ok(Some(route)) => return RouteLookup::Found(route, PathParams::default()),
+                Ok(None) => {}
+                Err(()) => return RouteLookup::NeedBody,
+            }
+        }
+    }
+
+    let patterns = match config.patterns.get(host) {
+        Some(patterns) => patterns,
+        None => return lookup_failed(method_matched, allow),
+    };
+    for pattern in patterns.value().iter() {
+        let params = match pattern.matches(url) {
+            Some(params) => params,
+            None => continue,
+        };
+        if let Some(routes) = config.routes.get(&(host.to_string(), pattern.pattern.clone())) {
+            match select(routes.value()) {
+                Ok(Some(route)) => return RouteLookup::Found(route, params),
+                Ok(None) => {}
+                Err(()) => return RouteLookup::NeedBody,
+            }
+        }
+    }
+
+    lookup_failed(method_matched, allow)
+}
+
+// no route is found, answer 405 if url is matched but method is not
+fn lookup_failed(method_matched: bool, allow: Vec<Method>) -> RouteLookup {
+    if method_matched || allow.is_empty() {
+        RouteLookup::NotFound
+    } else {
+        RouteLookup::MethodNotAllowed(allow)
+    }
+}
+
+/// answer request of a route listener, statistics, access log and journal of server are written
+pub(crate) async fn response(state: Arc<ServerState>, mut req: Request<Body>) -> Result<Response<MeteredBody>, Infallible> {
+    let start = Instant::now();
+    let method = req.method().clone();
+    let stats = &state.stats;
+    // request body is counted by its declared length, it may not be read
+    if let Some(length) = req.headers().get(CONTENT_LENGTH).and_then(|length| length.to_str().ok()?.parse::<u64>().ok()) {
+        stats.add_bytes_in(length);
+    }
+    // request is answered by routes at its start, even if yaml is reloaded meanwhile
+    let config = state.route_config();
+    let (host, host_slot) = resolve_host(&config, &req);
+    stats.inc_host_request(&host, host_slot);
+    stats.inc_version(req.version());
+    // request is consumed by routing, what access log and journal need is taken before
+    let uri = req.uri().path_and_query().map(|uri| uri.as_str()).unwrap_or("/").to_string();
+    let client = req.extensions().get::<ClientAddr>().map(|client| client.0);
+    let peer = req.extensions().get::<PeerAddr>().map(|peer| peer.0);
+    let access = if state.access_log.is_some() { Some((req.version(), req.headers().clone())) } else { None };
+    let journal = if state.journal.is_some() {
+        // only the start of body is read for journal, routes read it again along with the rest
+        let (body, body_length) = match read_body_head(&mut req, BODY_LIMIT).await {
+            Ok(head) => head,
+            Err(e) => {
+                println!("read request body failed: {}", e);
+                (Bytes::new(), 0)
+            }
+        };
+        Some((req.version(), req.headers().clone(), body, body_length))
+    } else {
+        None
+    };
+    let (route, matched, mut response) = route_request(req, &state, &config, &host).await;
+    let status = response.status().as_u16();
+    // a method not standard has no slot kept for unmatched and fallback responses
+    let no_slot = Slot::default();
+    let slot = match &matched {
+        Some(matched) => &matched.slot,
+        None if route == FALLBACK_ROUTE => state.fallback_slots.get(&method).unwrap_or(&no_slot),
+        None => state.unmatched_slots.get(&method).unwrap_or(&no_slot),
+    };
+    stats.inc_response(&method, &route, slot, status);
+    // recorded before response is sent, so a client sees its request once it's answered
+    if let (Some((version, headers, body, body_length)), Some(journal)) = (journal, &state.journal) {
+        journal.record(JournalEntry {
+            time: Local::now(),
+            client,
+            peer,
+            method: method.clone(),
+            uri: uri.clone(),
+            version,
+            headers,
+            body_length,
+            body,
+            host: host.clone(),
+            route: route.clone(),
+            status,
+        });
+    }
+    // delays are awaited by timers, worker threads serve other requests meanwhile
+    if let Some(delay) = response.extensions_mut().remove::<Delay>() {
+        if let Some(first_byte) = &delay.first_byte {
+            tokio::time::delay_for(first_byte.sample()).await;
+        }
+        if delay.chunk.is_some() {
+            let body = std::mem::replace(response.body_mut(), Body::empty());
+            *response.body_mut() = delay_chunks(body, delay);
+        }
+    }
+    // latency is recorded when response body is sent, so is access log
+    let (parts, body) = response.into_parts();
+    let mut body = MeteredBody::new(body, start, stats.clone());
+    if let (Some((version, headers)), Some(access_log)) = (access, state.access_log.clone()) {
+        let time = Local::now();
+        body = body.on_complete(Box::new(move |body_bytes_sent, request_time| {
+            access_log.write(AccessRecord { time, client, peer, method, uri, version, headers, host, route, status, body_bytes_sent, request_time });
+        }));
+    }
+    Ok(Response::from_parts(parts, body))
+}
+
+/// answer request by the matched route of virtual host, url pattern of the route and the route matched are returned along with response
+async fn route_request(mut req: Request<Body>, state: &ServerState, config: &RouteConfig, host: &str) -> (String, Option<Arc<RouteInfo>>, Response<Body>) {
+    let stats = &state.stats;
+    let url = req.uri().path().to_string();
+    let query = form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes())
+        .into_owned()
+        .collect::<Vec<(String, String)>>();
+    // start of request body is read only if some route needs to match it, the rest is left to route
+    let mut body: Option<Bytes> = None;
+    let (route, params) = loop {
+        let request = RequestParts { query: &query, headers: req.headers(), body: body.as_deref() };
+        match lookup_route(config, host, &url, req.method(), &request) {
+            RouteLookup::Found(route, params) => break (route, params),
+            RouteLookup::NeedBody => match read_body_head(&mut req, BODY_LIMIT).await {
+                Ok((head, _)) => body = Some(head),
+                Err(e) => {
+                    println!("read request body failed: {}", e);
+                    let response = Response::builder()
+                        .status(StatusCode::BAD_REQUEST)
+                        .body(Body::from("read request body failed"))
+                        .unwrap();
+                    return (UNMATCHED_ROUTE.to_string(), None, response);
+                }
+            },
+            RouteLookup::MethodNotAllowed(methods) => {
+                // list methods configured for this url
+                let allow = methods.iter().map(|method| method.as_str()).sorted().dedup().join(", ");
+                let response = Response::builder()
+                    .status(StatusCode::METHOD_NOT_ALLOWED)
+                    .header(ALLOW, allow)
+                    .body(Body::from("method for this request is not implemented"))
+                    .unwrap();
+                return (UNMATCHED_ROUTE.to_string(), None, response);
+            }
+            RouteLookup::NotFound => {
+                stats.inc_unmatched_path(&url);
+                if let Some(fallback) = &config.fallback {
+                    return (FALLBACK_ROUTE.to_string(), None, route_response(state, config, fallback, req).await);
+                }
+                // println!("url: {} not found", url);
+                let response = Response::builder()
+                    .status(StatusCode::NOT_FOUND)
+                    .body(Body::empty())
+                    .unwrap();
+                return (UNMATCHED_ROUTE.to_string(), None, response);
+            }
+        }
+    };
+    // values captured from url are kept along with request
+    req.extensions_mut().insert(params);
+    let response = route_response(state, config, &route, req).await;
+    (route.url.clone(), Some(route), response)
+}
+
+/// read request body, chunked body is counted as it has no declared length
+async fn read_body(req: &mut Request<Body>, stats: &Stats) -> Result<Bytes, hyper::Error> {
+    let bytes = hyper::body::to_bytes(req.body_mut()).await?;
+    if !req.headers().contains_key(CONTENT_LENGTH) {
+        stats.add_bytes_in(bytes.len() as u64);
+    }
+    Ok(bytes)
+}
+
+/// read at most limit bytes from the start of request body, the body left is streamed after them,
+/// so a big upload is never buffered, length is the declared one if body is longer than limit
+async fn read_body_head(req: &mut Request<Body>, limit: usize) -> Result<(Bytes, usize), hyper::Error> {
+    let mut body = std::mem::replace(req.body_mut(), Body::empty());
+    let mut head = Vec::new();
+    while head.len() <= limit {
+        match body.data().await {
+            Some(data) => head.extend_from_slice(&data?),
+            None => {
+                let head = Bytes::from(head);
+                *req.body_mut() = Body::from(head.clone());
+                let length = head.len();
+                return Ok((head, length));
+            }
+        }
+    }
+
+    let head = Bytes::from(head);
+    let (mut sender, rest) = Body::channel();
+    let read = head.clone();
+    tokio::spawn(async move {
+        if sender.send_data(read).await.is_err() {
+            return;
+        }
+        while let Some(data) = body.data().await {
+            match data {
+                Ok(data) => {
+                    if sender.send_data(data).await.is_err() {
+                        return;
+                    }
+                }
+                Err(e) => {
+                    println!("read request body failed: {}", e);
+                    sender.abort();
+                    return;
+                }
+            }
+        }
+    });
+    *req.body_mut() = rest;
+    let length = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok()?.parse::<usize>().ok())
+        .unwrap_or(head.len());
+    Ok((head.slice(..limit), length))
+}
+
+/// build response by route configuration, request body is whole even if its start is read for matching
+async fn route_response(state: &ServerState, config: &RouteConfig, route: &RouteInfo, req: Request<Body>) -> Response<Body> {
+    let url = req.uri().path().to_string();
+    let builder = hyper::Response::builder();
+    let builder = builder.status(route.status_code);
+    let mut builder = builder.header("Content-Type", route.mime_type.to_string());
+    let headers = builder.headers_mut().unwrap();
+    route.headers.iter().for_each(|(key, value)| {
+        headers.insert(key, value.clone());
+    });
+    // request values are collected only if route has templates
+    let mut rendered = None;
+    if !route.header_templates.is_empty() || matches!(route.body, Content::Template(..)) {
+        let query = form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes())
+            .into_owned()
+            .collect::<Vec<(String, String)>>();
+        let context = TemplateContext {
+            method: req.method(),
+            path: &url,
+            params: req.extensions().get::<PathParams>(),
+            query: &query,
+            headers: req.headers(),
+            client: req.extensions().get::<ClientAddr>().map(|client| client.0),
+            instance_id: state.instance_id(),
+        };
+        for (name, template) in route.header_templates.iter() {
+            match HeaderValue::from_str(&template.render(&context)) {
+                Ok(value) => {
+                    headers.insert(name, value);
+                }
+                Err(e) => println!("render header {} failed: {}", name, e),
+            }
+        }
+        if let Content::Template(template, _) = &route.body {
+            rendered = Some(template.render(&context));
+        }
+    }
+    let mut response = match &route.body {
+        Content::Cache(file) => {
+            let content = config.file_cache.get(file);
+            match content {
+                Some(content) => builder.body(Body::from(content.value().clone())).unwrap(),
+                None => {
+                    println!("url: {} cache not found", url);
+                    builder.status(StatusCode::NOT_FOUND).body(Body::from("not found")).unwrap()
+                }
+            }
+        }
+        Content::Content(content) => builder.body(Body::from(content.clone())).unwrap(),
+        Content::Template(..) => builder.body(Body::from(rendered.unwrap_or_default())).unwrap(),
+        Content::Echo => match echo_request(req, &state.stats).await {
+            Ok(echo) => builder.body(Body::from(echo.to_string())).unwrap(),
+            Err(e) => {
+                println!("read request body failed: {}", e);
+                Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Body::from("read request body failed"))
+                    .unwrap()
+            }
+        },
+        Content::File(file) => match stream_file(file).await {
+            Ok((length, body)) => builder.header(CONTENT_LENGTH, length).body(body).unwrap(),
+            Err(e) => {
+                println!("open file failed: {} => {:?}", file, e);
+                Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body(Body::from("open file failed"))
+                    .unwrap()
+            }
+        },
+    };
+    // delay is applied by response, after journal is recorded
+    if let Some(delay) = &route.delay {
+        response.extensions_mut().insert(delay.clone());
+    }
+    response
+}
+
+/// request as client sent it and as server received it, so what a proxy rewrites is seen,
+/// body is in text if it's short utf-8, otherwise its sha256 is given
+async fn echo_request(mut req: Request<Body>, stats: &Stats) -> Result<Value, hyper::Error> {
+    let body = read_body(&mut req, stats).await?;
+    // hyper keeps no wire order of headers, repeated ones are grouped by name
+    let headers: Vec<Value> = req
+        .headers()
+        .iter()
+        .map(|(name, value)| json!([name.as_str(), String::from_utf8_lossy(value.as_bytes())]))
+        .collect();
+    let text = match std::str::from_utf8(&body) {
+        Ok(text) if body.len() <= BODY_LIMIT => Some(text),
+        _ => None,
+    };
+    let sha256 = match text {
+        Some(_) => None,
+        None => Some(digest::digest(&digest::SHA256, &body).as_ref().iter().map(|byte| format!("{:02x}", byte)).collect::<String>()),
+    };
+    let tls = req.extensions().get::<TlsInfo>().map(|tls| {
+        json!({
+            "server_name": tls.server_name,
+            "version": tls.version,
+            "cipher_suite": tls.cipher_suite,
+            "alpn": tls.alpn,
+        })
+    });
+    Ok(json!({
+        "method": req.method().as_str(),
+        "uri": req.uri().to_string(),
+        "version": format!("{:?}", req.version()),
+        "headers": headers,
+        "body": text,
+        "body_length": body.len(),
+        "body_sha256": sha256,
+        "client": req.extensions().get::<ClientAddr>().map(|client| client.0.to_string()),
+        "peer": req.extensions().get::<PeerAddr>().map(|peer| peer.0.to_string()),
+        "tls": tls,
+    }))
+}
+
+/// resend body by chunks of delay, with a delay before every chunk except the first one,
+/// sending stops when client is gone
+fn delay_chunks(mut body: Body, delay: Delay) -> Body {
+    let (mut sender, delayed) = Body::channel();
+    tokio::spawn(async move {
+        let mut first = true;
+        while let Some(data) = body.data().await {
+            let data = match data {
+                Ok(data) => data,
+                Err(e) => {
+                    println!("read response body failed: {}", e);
+                    sender.abort();
+                    return;
+                }
+            };
+            let mut start = 0;
+            while start < data.len() {
+                let end = data.len().min(start + delay.chunk_size);
+                if let (false, Some(chunk)) = (first, &delay.chunk) {
+                    tokio::time::delay_for(chunk.sample()).await;
+                }
+                first = false;
+                if sender.send_data(data.slice(start..end)).await.is_err() {
+                    return;
+                }
+                start = end;
+            }
+        }
+    });
+    delayed
+}
+
+/// open a file and stream it to the response body by chunks,
+/// reading stops when client is gone, so big files are never loaded into memory
+async fn stream_file(path: &str) -> Result<(u64, Body), std::io::Error> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let length = file.metadata().await?.len();
+    let (mut sender, body) = Body::channel();
+    let path = path.to_string();
+    tokio::spawn(async move {
+        let mut buffer = vec![0u8; FILE_CHUNK_SIZE];
+        loop {
+            let n = match file.read(&mut buffer).await {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) => {
+                    println!("read file failed: {} => {:?}", path, e);
+                    sender.abort();
+                    break;
+                }
+            };
+            // wait until client receives previous chunk
+            if sender.send_data(Bytes::copy_from_slice(&buffer[..n])).await.is_err() {
+                break;
+            }
+        }
+    });
+    Ok((length, body))
+}
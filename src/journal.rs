@@ -0,0 +1,128 @@
+use crate::types::route::RequestParts;
+use crate::types::spec::FilterSpec;
+use chrono::{DateTime, Local, SecondsFormat};
+use hyper::body::Bytes;
+use hyper::header::HeaderMap;
+use hyper::{Method, Version};
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+/// body longer than it is kept partly
+pub const BODY_LIMIT: usize = 64 * 1024;
+
+/// a request answered by server
+pub struct JournalEntry {
+    pub time: DateTime<Local>,
+    // source in proxy protocol header if there is one
+    pub client: Option<SocketAddr>,
+    // tcp peer, the proxy if there is one
+    pub peer: Option<SocketAddr>,
+    pub method: Method,
+    // path and query
+    pub uri: String,
+    pub version: Version,
+    pub headers: HeaderMap,
+    // at most BODY_LIMIT bytes
+    pub body: Bytes,
+    // declared length of a long body, a long chunked body is known to be longer than BODY_LIMIT only
+    pub body_length: usize,
+    // virtual host answering the request
+    pub host: String,
+    // url pattern of the matched route, or unmatched
+    pub route: String,
+    pub status: u16,
+}
+
+impl JournalEntry {
+    fn path(&self) -> &str {
+        self.uri.split('?').next().unwrap_or("")
+    }
+
+    fn matches(&self, filter: &FilterSpec) -> bool {
+        if filter.method.as_ref().is_some_and(|method| method != self.method)
+            || filter.host.as_ref().is_some_and(|host| host != &self.host)
+            || filter.route.as_ref().is_some_and(|route| route != &self.route)
+            || filter.status_code.is_some_and(|status| status.as_u16() != self.status)
+        {
+            return false;
+        }
+        let url_matched = match &filter.url {
+            None => true,
+            Some((url, None)) => url == self.path(),
+            Some((_, Some(pattern))) => pattern.matches(self.path()).is_some(),
+        };
+        if !url_matched {
+            return false;
+        }
+
+        let query = self
+            .uri
+            .split_once('?')
+            .map(|(_, query)| form_urlencoded::parse(query.as_bytes()).into_owned().collect::<Vec<(String, String)>>())
+            .unwrap_or_default();
+        let request = RequestParts { query: &query, headers: &self.headers, body: Some(&self.body) };
+        request.matches(&filter.query, &filter.match_headers, &filter.match_body).unwrap_or(false)
+    }
+
+    /// headers are kept in order, a header repeated is listed repeatedly
+    pub fn to_json(&self, id: u64) -> Value {
+        let headers: Vec<Value> = self
+            .headers
+            .iter()
+            .map(|(name, value)| json!([name.as_str(), String::from_utf8_lossy(value.as_bytes())]))
+            .collect();
+        json!({
+            "id": id,
+            "time": self.time.to_rfc3339_opts(SecondsFormat::Millis, false),
+            "client": self.client.map(|client| client.to_string()),
+            "peer": self.peer.map(|peer| peer.to_string()),
+            "method": self.method.as_str(),
+            "uri": self.uri,
+            "version": format!("{:?}", self.version),
+            "headers": headers,
+            "body": String::from_utf8_lossy(&self.body),
+            "body_length": self.body_length,
+            "host": self.host,
+            "route": self.route,
+            "status": self.status,
+        })
+    }
+}
+
+// recorded requests by id, and id of the next one, ids are not reused after clear
+type Entries = (VecDeque<(u64, Arc<JournalEntry>)>, u64);
+
+/// the most recent requests, the oldest one is dropped when it's full
+pub struct Journal {
+    capacity: usize,
+    entries: Mutex<Entries>,
+}
+
+impl Journal {
+    /// capacity should not be 0
+    pub fn new(capacity: usize) -> Self {
+        Journal { capacity, entries: Mutex::new((VecDeque::with_capacity(capacity), 1)) }
+    }
+
+    pub fn record(&self, entry: JournalEntry) {
+        let (entries, next_id) = &mut *self.entries.lock().unwrap();
+        if entries.len() == self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back((*next_id, Arc::new(entry)));
+        *next_id += 1;
+    }
+
+    /// recorded requests matching filter, oldest first
+    pub fn find(&self, filter: &FilterSpec) -> Vec<(u64, Arc<JournalEntry>)> {
+        // entries are matched out of lock, so requests are not blocked by a slow matcher
+        let entries: Vec<_> = self.entries.lock().unwrap().0.iter().cloned().collect();
+        entries.into_iter().filter(|(_, entry)| entry.matches(filter)).collect()
+    }
+
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().0.clear();
+    }
+}
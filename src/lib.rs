@@ -0,0 +1,15 @@
+//! http/https test server answering requests by routes configured in yaml or in code,
+//! `TestServer` starts one in a tokio runtime, like in integration tests
+pub mod access_log;
+mod admin;
+mod handler;
+pub mod journal;
+pub mod loader;
+pub mod metrics;
+pub mod proxy;
+pub mod server;
+pub mod stats;
+pub mod tls;
+pub mod types;
+
+pub use server::{Route, ServerHandle, ServerState, TestServer};
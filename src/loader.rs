@@ -0,0 +1,227 @@
+use crate::proxy::ProxyProtocol;
+use crate::stats::Slot;
+use crate::types::config::RouteConfig;
+use crate::types::diagnostic::{child_path, Diagnostics};
+use crate::types::mime_types::MimeType;
+use crate::types::route::{Content, RouteInfo};
+use crate::types::spec::{ConfigSpec, RouteSpec, TlsSpec, DEFAULT_HOST};
+use crate::types::template::Template;
+use dashmap::DashMap;
+use itertools::Itertools;
+use hyper::body::Bytes;
+use hyper::header::{HeaderName, CONTENT_TYPE};
+use hyper::StatusCode;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use yaml_rust::{Yaml, YamlLoader};
+
+// if a file size small then MAX_FILE_CACHE_LENGTH, then this file will be cached
+const MAX_FILE_CACHE_LENGTH: u64 = 512 * 1024;
+
+/// read, check and build yaml file, along with tls options and proxy protocol of plain listener,
+/// error is returned only if yaml can not be read or parsed, other problems are in diagnostics
+pub fn load_yaml(path: &str) -> Result<(Option<TlsSpec>, Option<ProxyProtocol>, RouteConfig, Diagnostics), String> {
+    let source = fs::read_to_string(path).map_err(|e| format!("read yaml file failed: {}: {}", path, e))?;
+    let (mut spec, mut diagnostics) = parse_yaml(path, &source)?;
+    let tls = spec.tls.take();
+    let proxy_protocol = spec.proxy_protocol.take();
+    let config = build_route_config(spec, &mut diagnostics);
+    Ok((tls, proxy_protocol, config, diagnostics))
+}
+
+/// check yaml source, file is the name of it in diagnostics
+pub fn parse_yaml(file: &str, source: &str) -> Result<(ConfigSpec, Diagnostics), String> {
+    let docs = YamlLoader::load_from_str(source).map_err(|e| format!("parse yaml file failed: {}: {}", file, e))?;
+    let mut diagnostics = Diagnostics::new(file, source);
+    let spec = ConfigSpec::from_yaml(docs.first().unwrap_or(&Yaml::BadValue), &mut diagnostics);
+    Ok((spec, diagnostics))
+}
+
+/// whether configuration can be used, any warning is refused in strict mode
+pub fn is_config_accepted(diagnostics: &Diagnostics, strict: bool) -> bool {
+    !diagnostics.has_errors() && (diagnostics.is_empty() || !strict)
+}
+
+/// build routes, virtual hosts and fallback from checked yaml
+pub fn build_route_config(spec: ConfigSpec, diagnostics: &mut Diagnostics) -> RouteConfig {
+    let mut config = RouteConfig { hosts: spec.hosts.into_iter().map(|host| (host, Slot::default())).collect(), ..Default::default() };
+
+    for route in spec.routes.into_iter() {
+        insert_route(&config, route, diagnostics);
+    }
+
+    // patterns in same precedence keep the order in yaml
+    for mut patterns in config.patterns.iter_mut() {
+        patterns.sort_by(|a, b| a.precedence(b));
+    }
+
+    config.fallback = spec.fallback.map(|route| Arc::new(build_route_info(&config.file_cache, route, diagnostics)));
+    config
+}
+
+/// add route after routes of same url and method, like one more route at the end of yaml
+pub fn add_route(config: &RouteConfig, route: RouteSpec, diagnostics: &mut Diagnostics) {
+    let host = route.host.clone();
+    insert_route(config, route, diagnostics);
+    if let Some(mut patterns) = config.patterns.get_mut(&host) {
+        patterns.sort_by(|a, b| a.precedence(b));
+    }
+}
+
+// build route and insert it, patterns are left to be sorted
+fn insert_route(config: &RouteConfig, mut route: RouteSpec, diagnostics: &mut Diagnostics) {
+    let host = route.host.clone();
+    let url = route.url.clone();
+    let method = route.method.clone();
+    println!("insert url: {} {}{}", method, host, url);
+    if host != DEFAULT_HOST {
+        config.hosts.entry(host.clone()).or_default();
+    }
+    if let Some(pattern) = route.pattern.take() {
+        let mut patterns = config.patterns.entry(host.clone()).or_default();
+        if !patterns.iter().any(|element| element.pattern == pattern.pattern) {
+            patterns.push(pattern);
+        }
+    }
+    // same url may be configured by different methods,
+    // or by same method with different matchers
+    let route = Arc::new(build_route_info(&config.file_cache, route, diagnostics));
+    config.routes.entry((host, url)).or_default().entry(method).or_default().push(route);
+}
+
+/// build route from checked yaml, its file is loaded
+pub fn build_route_info(file_cache: &DashMap<String, Bytes>, mut route: RouteSpec, diagnostics: &mut Diagnostics) -> RouteInfo {
+    // configured status code and template are used only if body is ready,
+    // file can not be read is answered by 500 with the reason
+    let (mime_type, body, status_code, header_templates) = match parse_mime_and_body(file_cache, &route, diagnostics) {
+        Ok((mime_type, body)) if route.template => {
+            let body = parse_body_template(file_cache, &route, body, diagnostics);
+            let header_templates = parse_header_templates(&mut route, diagnostics);
+            (mime_type, body, route.status_code.unwrap_or(StatusCode::OK), header_templates)
+        }
+        Ok((mime_type, body)) => (mime_type, body, route.status_code.unwrap_or(StatusCode::OK), Vec::new()),
+        Err(message) => (MimeType::TextPlain, Content::Content(message), StatusCode::INTERNAL_SERVER_ERROR, Vec::new()),
+    };
+
+    // content type overrides the one guessed from file extension
+    let mut headers = route.headers;
+    if let Some(content_type) = route.content_type {
+        headers.insert(CONTENT_TYPE, content_type);
+    }
+
+    RouteInfo {
+        url: route.url,
+        method: route.method,
+        status_code,
+        mime_type,
+        headers,
+        header_templates,
+        body,
+        delay: route.delay,
+        query: route.query,
+        match_headers: route.match_headers,
+        match_body: route.match_body,
+        slot: Slot::default(),
+    }
+}
+
+// body or cached text file as template, file not cached is sent as it is
+fn parse_body_template(file_cache: &DashMap<String, Bytes>, route: &RouteSpec, body: Content, diagnostics: &mut Diagnostics) -> Content {
+    let (source, file, key) = match &body {
+        Content::Content(text) => (text.clone(), None, "body"),
+        Content::Cache(file) => match file_cache.get(file).map(|content| String::from_utf8(content.to_vec())) {
+            Some(Ok(text)) => (text, Some(file.clone()), "file"),
+            _ => {
+                diagnostics.warn(&child_path(&route.path, "file"), "template is ignored, file is not utf-8 text");
+                return body;
+            }
+        },
+        Content::File(_) => {
+            diagnostics.warn(&child_path(&route.path, "file"), "template is ignored, file is too large or not text");
+            return body;
+        }
+        Content::Echo | Content::Template(..) => return body,
+    };
+    match Template::parse(&source) {
+        Ok(template) => Content::Template(template, file),
+        Err(e) => {
+            diagnostics.error(&child_path(&route.path, key), e);
+            body
+        }
+    }
+}
+
+// header values with variables are moved out of headers, all values of such a header are templates
+fn parse_header_templates(route: &mut RouteSpec, diagnostics: &mut Diagnostics) -> Vec<(HeaderName, Template)> {
+    let names: Vec<HeaderName> = route
+        .headers
+        .iter()
+        .filter(|(_, value)| value.to_str().map(Template::has_variables).unwrap_or(false))
+        .map(|(name, _)| name.clone())
+        .dedup()
+        .collect();
+    let mut templates = Vec::new();
+    for name in names {
+        for value in route.headers.get_all(&name).iter() {
+            match Template::parse(value.to_str().unwrap_or("")) {
+                Ok(template) => templates.push((name.clone(), template)),
+                Err(e) => diagnostics.error(&child_path(&child_path(&route.path, "headers"), name.as_str()), e),
+            }
+        }
+        route.headers.remove(&name);
+    }
+    templates
+}
+
+// get mime type and body of route, small text file is cached, error is why file can not be read
+fn parse_mime_and_body(
+    file_cache: &DashMap<String, Bytes>,
+    route: &RouteSpec,
+    diagnostics: &mut Diagnostics,
+) -> Result<(MimeType, Content), String> {
+    if route.echo {
+        return Ok((MimeType::ApplicationJson, Content::Echo));
+    }
+    // file filed not found, use inline body, or empty body if there is no body
+    let full_path = match &route.file {
+        Some(file) => file,
+        None => return Ok((MimeType::TextPlain, Content::Content(route.body.clone().unwrap_or_default()))),
+    };
+    let key_path = child_path(&route.path, "file");
+    let abs_path = Path::new(full_path);
+    let failed = |diagnostics: &mut Diagnostics, message: String| {
+        diagnostics.warn(&key_path, message.clone());
+        Err(message)
+    };
+
+    // not file or no permmision to access
+    if !abs_path.is_file() {
+        return failed(diagnostics, format!("not a file: {:?}", abs_path));
+    }
+    // check file extension, only text file is cached
+    let mime_type = abs_path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| MimeType::from_str(extension).unwrap_or(MimeType::ApplicationOctetStream))
+        .unwrap_or(MimeType::ApplicationOctetStream);
+    if !mime_type.is_text() {
+        return Ok((MimeType::ApplicationOctetStream, Content::File(full_path.clone())));
+    }
+
+    let file_length = match fs::metadata(abs_path) {
+        Ok(meta) => meta.len(),
+        Err(e) => return failed(diagnostics, format!("get file metadata failed: {:?} => {:?}", abs_path, e)),
+    };
+    if file_length > MAX_FILE_CACHE_LENGTH {
+        return Ok((mime_type, Content::File(full_path.clone())));
+    }
+    match fs::read(abs_path) {
+        Ok(buffer) => {
+            file_cache.insert(full_path.clone(), Bytes::from(buffer));
+            Ok((mime_type, Content::Cache(full_path.clone())))
+        }
+        Err(e) => failed(diagnostics, format!("read file failed: {:?} => {:?}", abs_path, e)),
+    }
+}
@@ -5,45 +5,34 @@ extern crate lazy_static;
 extern crate dashmap;
 extern crate hyper;
 extern crate tokio;
-extern crate yaml_rust;
 extern crate chrono;
-extern crate netstat;
+extern crate test_server;
 
-mod types;
+mod export;
 
 use console::{Term, Color, style};
 use dashmap::DashMap;
-use hyper::header::{HeaderMap, HeaderName, HeaderValue};
-use hyper::service::{make_service_fn, service_fn};
-use hyper::{Body, Method, Request, Response, Server, StatusCode};
 use itertools::Itertools;
 use shellexpand;
 use std::boxed::Box;
-use std::collections::HashMap;
-use std::convert::Infallible;
 use std::env;
 use std::fs;
-use std::fs::File;
-use std::io::Read;
-use std::net::IpAddr;
-use std::path::Path;
+use std::net::{IpAddr, SocketAddr};
 use std::result::Result;
-use std::str::FromStr;
-use std::sync::Arc;
-use std::sync::Mutex;
-use std::sync::RwLock;
+use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::vec::Vec;
-use thread_id;
-use yaml_rust::yaml::Yaml::{Array, Hash};
-use yaml_rust::{Yaml, YamlLoader};
 use chrono::prelude::*;
-use netstat::*;
 use std::process;
 
-use crate::types::mime_types::MimeType;
-use crate::types::route::{Content, RouteInfo};
+use crate::export::{StatsExporter, StatsFormat, StatsRecord};
+use test_server::access_log::LogFormat;
+use test_server::proxy::ProxyProtocol;
+use test_server::loader::{is_config_accepted, load_yaml};
+use test_server::stats::{format_bytes, format_micros, HistogramSnapshot};
+use test_server::types::spec::TlsSpec;
+use test_server::{ServerState, TestServer};
 
 /// version
 const VERSION: &'static str = env!("CARGO_PKG_VERSION");
@@ -58,9 +47,23 @@ const AUTHOR: &'static str = env!("CARGO_PKG_AUTHORS");
 const KEY_IP: &'static str = "ip";
 const KEY_PORT: &'static str = "port";
 const KEY_INTERNAL: &'static str = "internal";
-
-// if a file size small then MAX_FILE_CACHE_LENGTH, then this file will be cached
-const MAX_FILE_CACHE_LENGTH: u64 = 512 * 1024;
+const KEY_TLS_PORT: &str = "tls_port";
+const KEY_TLS_CERT: &str = "tls_cert";
+const KEY_TLS_KEY: &str = "tls_key";
+const KEY_YAML: &str = "yaml";
+const KEY_ADMIN_PORT: &str = "admin_port";
+const KEY_TOP_UNMATCHED: &str = "top_unmatched";
+const KEY_CHECK_CONFIG: &str = "check_config";
+const KEY_STRICT: &str = "strict";
+const KEY_STATS_OUTPUT: &str = "stats_output";
+const KEY_STATS_FORMAT: &str = "stats_format";
+const KEY_NO_TUI: &str = "no_tui";
+const KEY_ACCESS_LOG: &str = "access_log";
+const KEY_JOURNAL: &str = "journal";
+const KEY_ACCESS_LOG_FORMAT: &str = "access_log_format";
+const KEY_PROXY_PROTOCOL: &str = "proxy_protocol";
+const KEY_TLS_PROXY_PROTOCOL: &str = "tls_proxy_protocol";
+const KEY_INSTANCE_ID: &str = "instance_id";
 
 // default statistics information refresh time
 const DEFAULT_STATS_REFRESH_INTERVAL: u64 = 1;
@@ -68,219 +71,240 @@ const DEFAULT_STATS_REFRESH_INTERVAL: u64 = 1;
 //default listen port
 const DEFAULT_LISTEN_PORT: u16 = 8088;
 
+// default tls listen port
+const DEFAULT_TLS_LISTEN_PORT: u16 = 8443;
+
+// interval to check whether yaml file is modified
+const YAML_WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
 lazy_static! {
     //parameters from command line
     static ref CONFIGURATION: DashMap<&'static str, String> = DashMap::new();
-    // yaml configuration
-    static ref YAML_CONFIG: Mutex<Vec<Yaml>> = Mutex::new(Vec::new());
-    // routes configuration
-    static ref ROUTES: DashMap<String, RouteInfo> = DashMap::new();
-    // file cache
-    static ref FILE_CACHE: DashMap<String, Arc<Box<Vec<u8>>>> = DashMap::new();
-    // statistics, structure
-    // thread_id 1 => status code 200 => 20
-    //             => status code 404 => 32
-    // thread_id 2 => status code 200 => 11
-    //             => status code 403 => 22
-    static ref STATISTICS: DashMap<usize, DashMap<u16, Box<u64>>> = DashMap::new();
-    // total connections, this variable stores all connections number that has been received from program start to now
-    static ref TOTAL_CONNECTIONS: RwLock<u64> = RwLock::new(0);
+    // held while statistics are printed, summary keeps it until exit
+    static ref TERMINAL: Mutex<()> = Mutex::new(());
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     env::set_var("RUST_BACKTRACE", "full");
+    let start = Instant::now();
     let ret = parse_args();
     if let Err(_) = ret {
         println!("init failed!");
         return Ok(());
     }
 
+    let ip: IpAddr = CONFIGURATION.get(KEY_IP).unwrap().value().parse().unwrap();
+    let port: u16 = CONFIGURATION.get(KEY_PORT).unwrap().value().parse().unwrap();
+    let mut server = TestServer::new().bind(SocketAddr::new(ip, port)).strict(CONFIGURATION.contains_key(KEY_STRICT));
+
     // init route information
-    if YAML_CONFIG.lock().unwrap().len() > 0 {
-        let yaml = YAML_CONFIG.lock().unwrap();
-        let doc = yaml.get(0);
-        match doc {
-            Some(doc) => {
-                init_route_by_yaml(doc);
+    let yaml = CONFIGURATION.get(KEY_YAML).map(|yaml| yaml.value().clone());
+    match yaml {
+        Some(yaml) => {
+            let (tls, proxy_protocol, config, diagnostics) = match load_yaml(&yaml) {
+                Ok(loaded) => loaded,
+                Err(e) => {
+                    println!("{}", e);
+                    process::exit(1);
+                }
+            };
+            print!("{}", diagnostics);
+            let accepted = is_config_accepted(&diagnostics, CONFIGURATION.contains_key(KEY_STRICT));
+            if CONFIGURATION.contains_key(KEY_CHECK_CONFIG) {
+                let routes = config.routes.iter().map(|routes| routes.value().values().map(Vec::len).sum::<usize>()).sum::<usize>();
+                println!(
+                    "{} routes, {} errors, {} warnings, configuration is {}",
+                    routes,
+                    diagnostics.errors(),
+                    diagnostics.warnings(),
+                    if accepted { "accepted" } else { "not accepted" }
+                );
+                process::exit(if accepted { 0 } else { 1 });
+            }
+            // same rule as check mode and reload, errors are refused, warnings are refused in strict mode
+            if !accepted {
+                println!("refuse to start, configuration is not accepted");
+                process::exit(1);
             }
-            None => {
-                println!("yaml error");
-                return Ok(())
+            if let Some(tls) = tls {
+                init_tls_by_yaml(&tls);
             }
+            // command line option takes precedence
+            if let Some(protocol) = proxy_protocol {
+                if !CONFIGURATION.contains_key(KEY_PROXY_PROTOCOL) {
+                    CONFIGURATION.insert(KEY_PROXY_PROTOCOL, format!("{:?}", protocol));
+                }
+            }
+            server = server.route_config(config);
         }
+        None if CONFIGURATION.contains_key(KEY_CHECK_CONFIG) => {
+            println!("no yaml configuration to check");
+            process::exit(1);
+        }
+        None => {}
     }
 
-    let addr = {
-        format!(
-            "{}:{}",
-            CONFIGURATION.get(KEY_IP).unwrap().value(),
-            CONFIGURATION.get(KEY_PORT).unwrap().value()
-        )
-    };
-    println!("{}", style(format!("listening on {}", addr)).bold().italic().yellow());
-    let addr = addr.parse().unwrap();
-
-    // And a MakeService to handle each connection...
-    let make_service = make_service_fn(|_conn| {
-        async {
-            inc_connections();
-            Ok::<_, Infallible>(service_fn(response))
+    // tls is enabled only when both certificate and private key are configured
+    match (CONFIGURATION.get(KEY_TLS_CERT), CONFIGURATION.get(KEY_TLS_KEY)) {
+        (Some(cert), Some(key)) => {
+            let tls_port = CONFIGURATION.get(KEY_TLS_PORT).and_then(|port| port.value().parse().ok()).unwrap_or(DEFAULT_TLS_LISTEN_PORT);
+            server = server.tls(cert.value(), key.value()).tls_bind(SocketAddr::new(ip, tls_port));
         }
-    });
-
-    create_stat_thread();
-
-    // Then bind and serve...
-    // wait for web service start
-    let server = Server::bind(&addr).tcp_keepalive(Some(Duration::from_secs(60))).http1_keepalive(true).serve(make_service);
-    server.await?;
+        (None, None) => {}
+        _ => {
+            println!("tls needs both certificate and private key");
+            return Ok(());
+        }
+    }
 
-    Ok(())
-}
+    // admin listener is apart from routes, so its requests are not in statistics
+    if let Some(admin_port) = CONFIGURATION.get(KEY_ADMIN_PORT) {
+        server = server.admin_bind(SocketAddr::new(ip, admin_port.value().parse().unwrap()));
+    }
+    if let Some(top_unmatched) = CONFIGURATION.get(KEY_TOP_UNMATCHED) {
+        server = server.top_unmatched(top_unmatched.value().parse().unwrap());
+    }
+    if let Some(path) = CONFIGURATION.get(KEY_ACCESS_LOG) {
+        let format = CONFIGURATION.get(KEY_ACCESS_LOG_FORMAT).unwrap().value().parse().unwrap();
+        server = server.access_log(path.value(), format);
+    }
+    if let Some(journal) = CONFIGURATION.get(KEY_JOURNAL) {
+        server = server.journal(journal.value().parse().unwrap());
+    }
+    // client address is taken from proxy protocol header, like behind haproxy or a cloud load balancer
+    if let Some(protocol) = CONFIGURATION.get(KEY_PROXY_PROTOCOL) {
+        server = server.proxy_protocol(protocol.value().parse().unwrap());
+    }
+    if let Some(protocol) = CONFIGURATION.get(KEY_TLS_PROXY_PROTOCOL) {
+        server = server.tls_proxy_protocol(protocol.value().parse().unwrap());
+    }
+    if let Some(instance_id) = CONFIGURATION.get(KEY_INSTANCE_ID) {
+        server = server.instance_id(instance_id.value());
+    }
 
-/// increase the response number by thread id and status code
-fn inc_response(thread_id: usize, status_code: u16) {
-    let thread_statistics = STATISTICS.get(&thread_id);
-    match thread_statistics {
-        Some(thread_statistics) => {
-            let thread_statistics = thread_statistics.value();
-            let http_statistics = thread_statistics.get_mut(&status_code);
-            match http_statistics {
-                Some(mut http_statistics) => {
-                    let ptr = http_statistics.value_mut();
-                    let count = **ptr;
-                    **ptr = count + 1;
-                }
-                None => {
-                    thread_statistics.insert(status_code.clone(), Box::new(1u64));
+    // statistics records are appended to file on every refresh
+    let exporter = match CONFIGURATION.get(KEY_STATS_OUTPUT).map(|path| path.value().clone()) {
+        Some(path) => {
+            let format = CONFIGURATION.get(KEY_STATS_FORMAT).unwrap().value().parse().unwrap();
+            match StatsExporter::open(&path, format) {
+                Ok(exporter) => Some(exporter),
+                Err(e) => {
+                    println!("open statistics output {} failed: {}", path, e);
+                    return Ok(());
                 }
             }
         }
-        None => {
-            let http_statistics = DashMap::new();
-            http_statistics.insert(status_code.clone(), Box::new(1u64));
-            STATISTICS.insert(thread_id, http_statistics);
+        None => None,
+    };
+
+    // Then bind and serve...
+    let handle = match server.start().await {
+        Ok(handle) => handle,
+        Err(e) => {
+            println!("{}", e);
+            return Ok(());
         }
+    };
+    println!("{}", style(format!("listening on {}", handle.addr())).bold().italic().yellow());
+    if let Some(tls_addr) = handle.tls_addr() {
+        println!("{}", style(format!("tls listening on {}", tls_addr)).bold().italic().yellow());
     }
-}
+    if let Some(admin_addr) = handle.admin_addr() {
+        println!("{}", style(format!("admin listening on {}", admin_addr)).bold().italic().yellow());
+    }
+    let state = handle.state().clone();
+    println!("instance id: {}", state.instance_id());
 
-/// if a new connection comming, increase the global count
-fn inc_connections() {
-    *TOTAL_CONNECTIONS.write().unwrap() += 1;
-}
+    if CONFIGURATION.contains_key(KEY_ACCESS_LOG) {
+        #[cfg(unix)]
+        tokio::spawn(reopen_access_log_on_user1(state.clone()));
+    }
 
-/// get total connections number
-fn get_total_connections() -> u64 {
-    *TOTAL_CONNECTIONS.read().unwrap()
-}
+    // live view needs a terminal, it garbles logs of systemd or docker
+    let tui = !CONFIGURATION.contains_key(KEY_NO_TUI) && Term::stderr().is_term();
+    create_stat_thread(state.clone(), tui, exporter);
 
-/// get response statistics
-/// status code -> count
-fn get_response_statistic() -> Box<HashMap<u16, u64>> {
-    let mut statistic = Box::new(HashMap::new());
-    STATISTICS.iter().for_each(|thread_statistic| {
-        thread_statistic.iter().for_each(|status_statistic| {
-            let code = *status_statistic.key();
-            let current_count = **status_statistic.value();
-            let status = statistic.get(&code);
-            match status {
-                Some(count) => {
-                    let total = current_count + count;
-                    statistic.insert(code, total);
-                }
-                None => {
-                    statistic.insert(code, current_count);
-                }
-            }
-        });
-    });
-    statistic
-}
+    // reload routes when yaml file is modified or SIGHUP is received
+    if CONFIGURATION.contains_key(KEY_YAML) {
+        create_yaml_watch_thread(state.clone());
+        #[cfg(unix)]
+        tokio::spawn(reload_on_hangup(state.clone()));
+    }
 
-/// get all connections by listening port
-fn get_connections_info_by_listen_port(listen_port: u16) -> Result<Vec<SocketInfo>, Error> {
-    let af_flags = AddressFamilyFlags::IPV4 | AddressFamilyFlags::IPV6;
-    let proto_flags = ProtocolFlags::TCP;
-    let sockets_info = get_sockets_info(af_flags, proto_flags)?;
-    let process_id = process::id();
-    let sockets_info = sockets_info.into_iter()
-        .filter(|si| {
-            match &si.protocol_socket_info {
-                ProtocolSocketInfo::Tcp(tcp_si) => {
-                    tcp_si.local_port == listen_port && si.associated_pids.contains(&process_id)
-                }
-                _ => false
-            }
-        })
-        .collect::<Vec<SocketInfo>>();
-    Ok(sockets_info)
+    shutdown_signal().await;
+    print_summary(&state, start.elapsed(), tui);
+
+    Ok(())
 }
 
-async fn response(req: Request<Body>) -> Result<Response<Body>, Infallible> {
-    let url = req.uri().path().to_string();
-    let thread_id: usize = thread_id::get();
-    match ROUTES.get(&url) {
-        Some(route) => {
-            let route = route.value();
-            if route.method == req.method() {
-                let builder = hyper::Response::builder();
-                let builder = builder.status(route.status_code);
-                let mut builder = builder.header("Content-Type", route.mime_type.to_string());
-                let headers = builder.headers_mut().unwrap();
-                route.headers.iter().for_each(|(key, value)| {
-                    headers.insert(key, value.clone());
-                });
-                match &route.body {
-                    Content::Cache => {
-                        let content = FILE_CACHE.get(&url);
-                        match content {
-                            Some(content) => {
-                                let len = content.len();
-                                let raw = content.as_ptr();
-                                unsafe {
-                                    inc_response(thread_id, route.status_code.as_u16());
-                                    Ok(builder
-                                        .body(Body::from(std::slice::from_raw_parts(raw, len)))
-                                        .unwrap())
-                                }
-                            }
-                            None => {
-                                println!("url: {} cache not found", &url);
-                                inc_response(thread_id, StatusCode::NOT_FOUND.as_u16());
-                                Ok(builder
-                                    .status(StatusCode::NOT_FOUND)
-                                    .body(Body::from("not found"))
-                                    .unwrap())
-                            }
-                        }
-                    }
-                    Content::Content(content) => {
-                        inc_response(thread_id, route.status_code.as_u16());
-                        Ok(builder.body(Body::from(content.clone())).unwrap())
-                    }
-                    Content::File(file) => {
-                        inc_response(thread_id, route.status_code.as_u16());
-                        Ok(builder.body(Body::from(file.clone())).unwrap())
-                    }
-                }
-            } else {
-                inc_response(thread_id, StatusCode::METHOD_NOT_ALLOWED.as_u16());
-                Ok(Response::builder()
-                    .status(StatusCode::METHOD_NOT_ALLOWED)
-                    .body(Body::from("method for this request is not implemented"))
-                    .unwrap())
+/// wait for ctrl-c, or SIGTERM sent by systemd or docker
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        let mut terminate = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(terminate) => terminate,
+            Err(e) => {
+                println!("listen SIGTERM failed: {}", e);
+                let _ = tokio::signal::ctrl_c().await;
+                return;
             }
+        };
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {},
+            _ = terminate.recv() => {},
         }
-        None => {
-            inc_response(thread_id, StatusCode::NOT_FOUND.as_u16());
-            // println!("url: {} not found", url);
-            Ok(Response::builder()
-                .status(StatusCode::NOT_FOUND)
-                .body(Body::empty())
-                .unwrap())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+/// print statistics from start when server is shutting down
+fn print_summary(state: &ServerState, uptime: Duration, tui: bool) {
+    let stats = state.stats();
+    // statistics thread stops printing, so it does not overwrite summary
+    let _terminal = TERMINAL.lock().unwrap();
+    if tui {
+        let _ = Term::stderr().show_cursor();
+    }
+    let responses = stats.response_statistic().into_iter().sorted().collect::<Vec<_>>();
+    let latency = stats.latency_snapshot();
+    let (bytes_in, bytes_out) = stats.total_bytes();
+    println!("*************** summary ***************");
+    println!("[Uptime] {}", format_micros(uptime.as_micros() as u64));
+    println!("[Connections from start] {}", stats.total_connections());
+    if proxy_protocol_enabled() {
+        println!("[Proxy protocol errors] {}", stats.proxy_protocol_errors());
+    }
+    println!("[Requests] {}", responses.iter().map(|(_, count)| count).sum::<u64>());
+    println!("[Bytes in] {} [Bytes out] {}", format_bytes(bytes_in as f64), format_bytes(bytes_out as f64));
+    println!(
+        "[Latency] p50 {} p90 {} p99 {}",
+        format_micros(latency.percentile(50.0)),
+        format_micros(latency.percentile(90.0)),
+        format_micros(latency.percentile(99.0))
+    );
+    println!(
+        "[Versions] {}",
+        stats.version_statistic().iter().map(|(version, count)| format!("{:?}: {}", version, count)).join(", ")
+    );
+    println!("-----------------------------------");
+    for (code, count) in responses.iter() {
+        println!("[{}] {}", code, count);
+    }
+    if !state.route_config().hosts.is_empty() {
+        for (host, count) in stats.host_statistic().iter() {
+            println!("[{}] {}", host, count);
         }
     }
+    for ((method, route), statuses) in &stats.route_statistic().iter().group_by(|(method, route, _, _)| (method, route)) {
+        println!("[{} {}] {}", method, route, statuses.map(|(_, _, code, count)| format!("{}: {}", code, count)).join(", "));
+    }
+    for (path, count) in state.top_unmatched_paths().iter() {
+        println!("[unmatched {}] {}", path, count);
+    }
 }
 
 fn write_term(term: &Term, msg: &str, term_line_num: usize) -> usize {
@@ -293,67 +317,24 @@ fn write_term(term: &Term, msg: &str, term_line_num: usize) -> usize {
     }
 }
 
-fn get_netstat_info() -> (usize, usize, usize) {
-    let listen_port = CONFIGURATION.get(KEY_PORT).unwrap().value().parse::<u16>().unwrap_or(DEFAULT_LISTEN_PORT);
-    let sockets_info = match get_connections_info_by_listen_port(listen_port) {
-        Ok(sockets_info) => sockets_info,
-        Err(e) => {
-            println!("Error: get sockets info failed: {:?}", e);
-            Vec::new()
-        }
-    };
-
-    // syn-recvd
-    let connecting_num = sockets_info.iter().filter(|si| {
-        match &si.protocol_socket_info {
-            ProtocolSocketInfo::Tcp(tcp_si) => {
-                match tcp_si.state {
-                    TcpState::SynReceived => true,
-                    _ => false
-                }
-            }
-            _ => false
-        }
-    }).count();
-
-    // is closing
-    let closing_num = sockets_info.iter().filter(|si| {
-        match &si.protocol_socket_info {
-            ProtocolSocketInfo::Tcp(tcp_si) => {
-                match tcp_si.state {
-                    TcpState::FinWait1 | TcpState::FinWait2 | TcpState::CloseWait | TcpState::Closing |
-                    TcpState::LastAck | TcpState::TimeWait => true,
-                    _ => false
-                }
-            }
-            _ => false
-        }
-    }).count();
-
-    // established
-    let established_num = sockets_info.iter().filter(|si| {
-        match &si.protocol_socket_info {
-            ProtocolSocketInfo::Tcp(tcp_si) => {
-                match tcp_si.state {
-                    TcpState::Established => true,
-                    _ => false
-                }
-            }
-            _ => false
-        }
-    }).count();
-
-    (connecting_num, closing_num, established_num)
-}
-
-/// create statistics thread
-fn create_stat_thread() {
+/// create statistics thread, it refreshes live view on terminal if tui is set, otherwise prints a log line,
+/// records are also written to exporter if given
+fn create_stat_thread(state: Arc<ServerState>, tui: bool, mut exporter: Option<StatsExporter>) {
     thread::spawn(move || {
+        let stats = state.stats();
         // terminal to show statistics
         let term = console::Term::stderr();
-        term.hide_cursor().unwrap();
+        if tui {
+            if let Err(e) = term.hide_cursor() {
+                println!("hide cursor failed: {}", e);
+            }
+        }
         // terminal line number
         let mut term_line_num = 0;
+        // values at last refresh, rates are computed from them
+        let mut last_time = Instant::now();
+        let mut last_latency = HistogramSnapshot::empty();
+        let mut last_bytes = (0, 0);
         loop {
             // sleep
             let durection = Duration::from_secs(
@@ -366,7 +347,82 @@ fn create_stat_thread() {
             );
             thread::sleep(durection);
 
-            let (connecting, closing, established) = get_netstat_info();
+            let (connecting, closing, established) = state.connection_states();
+
+            // requests and bytes of this interval
+            let elapsed = last_time.elapsed().as_secs_f64();
+            last_time = Instant::now();
+            let latency_snapshot = stats.latency_snapshot();
+            let latency = latency_snapshot.since(&last_latency);
+            last_latency = latency_snapshot;
+            let latency_max = stats.take_latency_max();
+            let bytes = stats.total_bytes();
+            // bytes may be reset by admin api
+            let bytes_rate = (bytes.0.saturating_sub(last_bytes.0) as f64 / elapsed, bytes.1.saturating_sub(last_bytes.1) as f64 / elapsed);
+            last_bytes = bytes;
+            // percentile is the upper bound of a bucket, it may be bigger than max
+            let percentiles: Vec<_> = [("p50", latency.percentile(50.0)), ("p90", latency.percentile(90.0)), ("p99", latency.percentile(99.0)), ("max", latency_max)]
+                .iter()
+                .map(|(name, micros)| (*name, (*micros).min(latency_max)))
+                .collect();
+            let resp_status_statistic: Vec<_> = stats.response_statistic().into_iter().sorted_by(|a, b| Ord::cmp(&a.0, &b.0)).collect();
+            let host_statistic = stats.host_statistic();
+            let route_statistic = stats.route_statistic();
+            let unmatched_paths = state.top_unmatched_paths();
+            // requests by http version from start, like HTTP/1.1: 3, HTTP/2.0: 1
+            let versions: Vec<_> = stats.version_statistic().into_iter().map(|(version, count)| (format!("{:?}", version), count)).collect();
+
+            if let Some(writer) = exporter.as_mut() {
+                let record = StatsRecord {
+                    time: Local::now(),
+                    connections: stats.total_connections(),
+                    proxy_protocol_errors: stats.proxy_protocol_errors(),
+                    connecting,
+                    closing,
+                    established,
+                    requests_per_second: latency.count() as f64 / elapsed,
+                    bytes_in_per_second: bytes_rate.0,
+                    bytes_out_per_second: bytes_rate.1,
+                    latency: percentiles.clone(),
+                    versions: versions.clone(),
+                    statuses: resp_status_statistic.clone(),
+                    hosts: host_statistic.clone(),
+                    routes: route_statistic.clone(),
+                    unmatched_paths: unmatched_paths.clone(),
+                };
+                if let Err(e) = writer.write(&record) {
+                    println!("write statistics output failed: {}", e);
+                }
+            }
+
+            let _terminal = TERMINAL.lock().unwrap();
+            if !tui {
+                let percentiles = percentiles.iter().map(|(name, micros)| format!("{} {}", name, format_micros(*micros))).join(" ");
+                let versions = versions.iter().map(|(version, count)| format!("{}: {}", version, count)).join(", ");
+                let statuses = resp_status_statistic.iter().map(|(code, count)| format!("{}: {}", code, count)).join(", ");
+                // errors are shown only if a listener expects proxy protocol
+                let proxy_protocol_errors = if proxy_protocol_enabled() {
+                    format!(", proxy protocol errors: {}", stats.proxy_protocol_errors())
+                } else {
+                    String::new()
+                };
+                println!(
+                    "[{}] connections: {}{}, connecting: {}, closing: {}, established: {}, requests/s: {:.1}, bytes in/s: {}, bytes out/s: {}, latency: {}, versions: {}, statuses: {}",
+                    Local::now().format("%Y-%m-%d %H:%M:%S"),
+                    stats.total_connections(),
+                    proxy_protocol_errors,
+                    connecting,
+                    closing,
+                    established,
+                    latency.count() as f64 / elapsed,
+                    format_bytes(bytes_rate.0),
+                    format_bytes(bytes_rate.1),
+                    percentiles,
+                    versions,
+                    statuses
+                );
+                continue;
+            }
 
             // clear termimal output
             match term.clear_last_lines(term_line_num) {
@@ -378,9 +434,16 @@ fn create_stat_thread() {
                         style("***************").bold().cyan()), term_line_num.clone());
                     term_line_num = write_term(
                         &term,
-                        &format!("[{}] {}", style("Connections from start").bold().italic().yellow().bg(Color::Black), style(get_total_connections()).bg(Color::Black).white().bold()),
+                        &format!("[{}] {}", style("Connections from start").bold().italic().yellow().bg(Color::Black), style(stats.total_connections()).bg(Color::Black).white().bold()),
                         term_line_num.clone(),
                     );
+                    if proxy_protocol_enabled() {
+                        term_line_num = write_term(
+                            &term,
+                            &format!("[{}] {}", style("Proxy protocol errors").bold().italic().yellow().bg(Color::Black), style(stats.proxy_protocol_errors()).bg(Color::Black).white().bold()),
+                            term_line_num,
+                        );
+                    }
                     term_line_num = write_term(
                         &term,
                         &format!("[{}] {}", style("Connecting").bold().italic().yellow().bg(Color::Black), style(connecting).bg(Color::Black).white().bold()),
@@ -396,23 +459,64 @@ fn create_stat_thread() {
                         &format!("[{}] {}", style("Established").bold().italic().yellow().bg(Color::Black), style(established).bg(Color::Black).white().bold()),
                         term_line_num.clone(),
                     );
+                    term_line_num = write_term(
+                        &term,
+                        &format!("[{}] {:.1}", style("Requests/s").bold().italic().yellow().bg(Color::Black), style(latency.count() as f64 / elapsed).bg(Color::Black).white().bold()),
+                        term_line_num,
+                    );
+                    term_line_num = write_term(
+                        &term,
+                        &format!("[{}] {} [{}] {}", style("Bytes in/s").bold().italic().yellow().bg(Color::Black), style(format_bytes(bytes_rate.0)).bg(Color::Black).white().bold(),
+                            style("Bytes out/s").bold().italic().yellow().bg(Color::Black), style(format_bytes(bytes_rate.1)).bg(Color::Black).white().bold()),
+                        term_line_num,
+                    );
+                    let percentiles = percentiles.iter().map(|(name, micros)| format!("{} {}", name, format_micros(*micros))).join(" ");
+                    term_line_num = write_term(
+                        &term,
+                        &format!("[{}] {}", style("Latency").bold().italic().yellow().bg(Color::Black), style(percentiles).bg(Color::Black).white().bold()),
+                        term_line_num,
+                    );
+                    let versions = versions.iter().map(|(version, count)| format!("{}: {}", version, count)).join(", ");
+                    term_line_num = write_term(
+                        &term,
+                        &format!("[{}] {}", style("Versions").bold().italic().yellow().bg(Color::Black), style(versions).bg(Color::Black).white().bold()),
+                        term_line_num,
+                    );
                     term_line_num =
                         write_term(&term, &format!("{}", style("-----------------------------------").green()), term_line_num.clone());
-                    //get response statistics
-                    let iter = get_response_statistic().into_iter();
-                    let resp_status_statistic: Vec<_> = iter.collect();
-
                     // response statistics start from third bar
-                    for (_, element) in resp_status_statistic
-                        .into_iter()
-                        .sorted_by(|a, b| Ord::cmp(&a.0, &b.0))
-                        .into_iter()
-                        .enumerate()
-                    {
-                        let (code, count) = element;
+                    for (code, count) in resp_status_statistic.iter() {
                         term_line_num = write_term(&term, &format!("[{}] {}", style(code).bold().italic().yellow().bg(Color::Black), 
                             style(count).bg(Color::Black).white().bold()), term_line_num.clone());
                     }
+
+                    // requests of virtual hosts, only if virtual hosts are configured
+                    if !state.route_config().hosts.is_empty() {
+                        term_line_num = write_term(&term, &format!("{}", style("-----------------------------------").green()), term_line_num);
+                        for (host, count) in host_statistic.iter() {
+                            term_line_num = write_term(&term, &format!("[{}] {}", style(host).bold().italic().yellow().bg(Color::Black),
+                                style(count).bg(Color::Black).white().bold()), term_line_num);
+                        }
+                    }
+
+                    // responses of every route, one line per method and route
+                    if !route_statistic.is_empty() {
+                        term_line_num = write_term(&term, &format!("{}", style("-----------------------------------").green()), term_line_num);
+                    }
+                    for ((method, route), statuses) in &route_statistic.iter().group_by(|(method, route, _, _)| (method, route)) {
+                        let statuses = statuses.map(|(_, _, code, count)| format!("{}: {}", code, count)).join(", ");
+                        term_line_num = write_term(&term, &format!("[{} {}] {}", style(method).bold().italic().yellow().bg(Color::Black),
+                            style(route).bold().italic().yellow().bg(Color::Black), style(statuses).bg(Color::Black).white().bold()), term_line_num);
+                    }
+
+                    // paths requested most but matching no route
+                    if !unmatched_paths.is_empty() {
+                        term_line_num = write_term(&term, &format!("{}", style("-----------------------------------").green()), term_line_num);
+                    }
+                    for (path, count) in unmatched_paths.iter() {
+                        term_line_num = write_term(&term, &format!("[{} {}] {}", style("unmatched").bold().italic().red().bg(Color::Black),
+                            style(path).bold().italic().yellow().bg(Color::Black), style(count).bg(Color::Black).white().bold()), term_line_num);
+                    }
                 }
                 Err(e) => {
                     println!("clear term failed: {}", e);
@@ -423,6 +527,83 @@ fn create_stat_thread() {
     });
 }
 
+/// read yaml file again and swap in routes built from it,
+/// current routes are kept if yaml is not accepted, tls options and proxy protocol are not reloaded
+fn reload_yaml(state: &ServerState) {
+    let path = match CONFIGURATION.get(KEY_YAML) {
+        Some(path) => path.value().clone(),
+        None => return,
+    };
+    let (_, _, config, diagnostics) = match load_yaml(&path) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            println!("reload yaml failed, {}", e);
+            return;
+        }
+    };
+    print!("{}", diagnostics);
+    // empty file is rejected too, editor may truncate file before writing it
+    if !state.is_config_accepted(&diagnostics) {
+        println!("reload yaml failed, current routes are kept: {}", path);
+        return;
+    }
+    state.set_route_config(config);
+    println!("yaml reloaded: {}", path);
+}
+
+/// create thread to reload yaml when its modified time is changed
+fn create_yaml_watch_thread(state: Arc<ServerState>) {
+    let path = match CONFIGURATION.get(KEY_YAML) {
+        Some(path) => path.value().clone(),
+        None => return,
+    };
+    thread::spawn(move || {
+        let modified = || fs::metadata(&path).and_then(|meta| meta.modified()).ok();
+        let mut last_modified = modified();
+        loop {
+            thread::sleep(YAML_WATCH_INTERVAL);
+            // file may be missing for a moment while editor replaces it
+            let current = modified();
+            if current.is_some() && current != last_modified {
+                last_modified = current;
+                reload_yaml(&state);
+            }
+        }
+    });
+}
+
+/// reload yaml every time SIGHUP is received
+#[cfg(unix)]
+async fn reload_on_hangup(state: Arc<ServerState>) {
+    let mut hangup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            println!("listen SIGHUP failed: {}", e);
+            return;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        reload_yaml(&state);
+    }
+}
+
+/// reopen access log every time SIGUSR1 is received, logrotate sends it after moving the file away
+#[cfg(unix)]
+async fn reopen_access_log_on_user1(state: Arc<ServerState>) {
+    let mut user1 = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::user_defined1()) {
+        Ok(user1) => user1,
+        Err(e) => {
+            println!("listen SIGUSR1 failed: {}", e);
+            return;
+        }
+    };
+    while user1.recv().await.is_some() {
+        if let Err(e) = state.reopen_access_log() {
+            println!("reopen access log failed: {}", e);
+        }
+    }
+}
+
 /// init configuration
 fn parse_args() -> std::result::Result<(), Box<dyn std::error::Error>> {
     // build arguments parser
@@ -435,6 +616,22 @@ fn parse_args() -> std::result::Result<(), Box<dyn std::error::Error>> {
         (@arg port: -p --port +takes_value "listening port number")
         (@arg interval: -i --interval +takes_value "refresh statistics information interval, default is 1 second")
         (@arg yaml: -y --yaml +takes_value "yaml configuration, configure urls and files mapping")
+        (@arg tls_port: --("tls-port") +takes_value "tls listening port number, default is 8443")
+        (@arg tls_cert: --("tls-cert") +takes_value "tls certificate chain file in pem format")
+        (@arg tls_key: --("tls-key") +takes_value "tls private key file in pem format")
+        (@arg check_config: --("check-config") "check yaml configuration and exit, exit code is 1 if it's not accepted")
+        (@arg admin_port: --("admin-port") +takes_value "admin listening port number, serves /metrics, /routes, /requests and /stats/reset, disabled if not given")
+        (@arg top_unmatched: --("top-unmatched") +takes_value "number of the most requested paths matching no route to show, default is 10")
+        (@arg strict: --strict "refuse yaml configuration with any warning too, on start and on reload")
+        (@arg stats_output: --("stats-output") +takes_value "file to append statistics to on every refresh")
+        (@arg stats_format: --("stats-format") +takes_value "format of statistics output, json or csv, default is json")
+        (@arg access_log: --("access-log") +takes_value "file to append access log to, reopened on SIGUSR1")
+        (@arg access_log_format: --("access-log-format") +takes_value "format of access log, combined, json or a template like '$remote_addr \"$request\" $status $request_time $route', default is combined")
+        (@arg proxy_protocol: --("proxy-protocol") +takes_value "proxy protocol header expected on listening port, v1, v2 or optional, client address is taken from it")
+        (@arg tls_proxy_protocol: --("tls-proxy-protocol") +takes_value "proxy protocol header expected on tls listening port before handshake, v1, v2 or optional")
+        (@arg instance_id: --("instance-id") +takes_value "id of this server in response templates, like backend-2, default is a random uuid")
+        (@arg journal: --journal +takes_value "number of the most recent requests recorded for verification by admin api, disabled if not given")
+        (@arg no_tui: --("no-tui") "print statistics as plain log lines instead of live view, default if stderr is not a terminal")
     ).get_matches();
 
     // parse or set default ipaddress
@@ -472,365 +669,163 @@ fn parse_args() -> std::result::Result<(), Box<dyn std::error::Error>> {
     };
     CONFIGURATION.insert(KEY_INTERNAL, interval.to_string());
 
-    // get yaml configuration
-    let yaml = matches.value_of("yaml");
-    if yaml.is_none() {
-        return Ok(());
+    // parse tls options, yaml tls block is used for those not given here
+    if let Some(tls_port) = matches.value_of("tls_port") {
+        let tls_port = match tls_port.parse::<u16>() {
+            Ok(tls_port) => tls_port,
+            Err(e) => {
+                println!("parse tls port failed: {:?}", e);
+                return Err(Box::new(e));
+            }
+        };
+        CONFIGURATION.insert(KEY_TLS_PORT, tls_port.to_string());
     }
-    // yaml file path
-    let yaml = shellexpand::full(yaml.unwrap());
-    let yaml = match yaml {
-        Ok(yaml) => yaml.to_string(),
-        Err(e) => {
-            println!("expand yaml file path failed: {:?}", e);
-            return Err(Box::new(e));
-        }
-    };
-
-    println!("yaml path: {}", yaml);
-
-    // read yaml file to string
-    let yaml = match fs::read_to_string(yaml) {
-        Ok(yaml) => yaml,
-        Err(e) => {
-            println!("read yaml file failed: {:?}", e);
-            return Err(Box::new(e));
+    for (arg, key) in [("tls_cert", KEY_TLS_CERT), ("tls_key", KEY_TLS_KEY)].iter() {
+        if let Some(path) = matches.value_of(arg) {
+            match shellexpand::full(path) {
+                Ok(path) => {
+                    CONFIGURATION.insert(key, path.to_string());
+                }
+                Err(e) => {
+                    println!("expand {} path failed: {:?}", arg, e);
+                    return Err(Box::new(e));
+                }
+            }
         }
-    };
+    }
 
-    parse_yaml(&yaml)?;
+    if let Some(admin_port) = matches.value_of("admin_port") {
+        let admin_port = match admin_port.parse::<u16>() {
+            Ok(admin_port) => admin_port,
+            Err(e) => {
+                println!("parse admin port failed: {:?}", e);
+                return Err(Box::new(e));
+            }
+        };
+        CONFIGURATION.insert(KEY_ADMIN_PORT, admin_port.to_string());
+    }
 
-    Ok(())
-}
+    if let Some(top_unmatched) = matches.value_of("top_unmatched") {
+        let top_unmatched = match top_unmatched.parse::<usize>() {
+            Ok(top_unmatched) => top_unmatched,
+            Err(e) => {
+                println!("parse top unmatched failed: {:?}", e);
+                return Err(Box::new(e));
+            }
+        };
+        CONFIGURATION.insert(KEY_TOP_UNMATCHED, top_unmatched.to_string());
+    }
 
-// parse yaml
-fn parse_yaml(yaml: &str) -> Result<(), Box<dyn std::error::Error>> {
-    // parse yaml string
-    let docs = match YamlLoader::load_from_str(yaml) {
-        Ok(yaml) => yaml,
+    let stats_format = match matches.value_of("stats_format").unwrap_or("json").parse::<StatsFormat>() {
+        Ok(stats_format) => stats_format,
         Err(e) => {
-            println!("parse yaml faile failed: {:?}", e);
-            return Err(Box::new(e));
+            println!("parse stats format failed: {}", e);
+            return Err(e.into());
         }
     };
-    *YAML_CONFIG.lock().unwrap() = docs;
-    Ok(())
-}
-
-// init route from yaml
-fn init_route_by_yaml(yaml: &Yaml) {
-    let yaml = match yaml {
-        Hash(yaml) => yaml,
-        _ => return,
-    };
-
-    for (key, value) in yaml.iter() {
-        // get array
-        let value = match value {
-            Array(yaml) => yaml,
-            _ => {
-                println!("the method's elements should be an array");
-                continue;
+    CONFIGURATION.insert(KEY_STATS_FORMAT, format!("{:?}", stats_format));
+    for (arg, key) in [("stats_output", KEY_STATS_OUTPUT), ("access_log", KEY_ACCESS_LOG)].iter() {
+        if let Some(path) = matches.value_of(arg) {
+            match shellexpand::full(path) {
+                Ok(path) => {
+                    CONFIGURATION.insert(key, path.to_string());
+                }
+                Err(e) => {
+                    println!("expand {} path failed: {:?}", arg, e);
+                    return Err(Box::new(e));
+                }
             }
-        };
+        }
+    }
 
-        //  build methods routes
-        let key = key.as_str().unwrap_or("GET").to_uppercase();
-        let method = match Method::from_str(&key) {
-            Ok(method) => method,
+    if let Some(journal) = matches.value_of("journal") {
+        let journal = match journal.parse::<usize>() {
+            Ok(journal) if journal > 0 => journal,
+            Ok(_) => {
+                println!("journal size should be greater than 0");
+                return Err("journal size should be greater than 0".into());
+            }
             Err(e) => {
-                println!("method error: {}", e);
-                continue;
+                println!("parse journal size failed: {:?}", e);
+                return Err(Box::new(e));
             }
         };
+        CONFIGURATION.insert(KEY_JOURNAL, journal.to_string());
+    }
 
-        // initialize keys
-        let url_key = yaml_rust::Yaml::String("url".to_string());
-        let file_key = yaml_rust::Yaml::String("file".to_string());
-        let headers_key = yaml_rust::Yaml::String("headers".to_string());
-        //        let status_code_key = yaml_rust::Yaml::String("status_code".to_string());
-
-        // filter from array that has url filed.
-        let value = value
-            .iter()
-            .filter(|element| match element {
-                Hash(element) => element.contains_key(&url_key),
-                _ => {
-                    println!("request configuration should be hash type: {:?}", element);
-                    false
-                }
-            })
-            .collect::<Vec<&Yaml>>();
-
-        // traverse all requests configuration
-        for req in value.into_iter() {
-            match req {
-                Hash(element) => {
-                    // get url
-                    let url = element.get(&url_key).unwrap();
-                    let url = match url {
-                        yaml_rust::yaml::Yaml::String(url) => url.clone(),
-                        _ => {
-                            println!("url not string: {:?}", url);
-                            continue;
-                        }
-                    };
-
-                    // mime type, body and status code
-                    let (mime_type, body, status_code) =
-                        match parse_mime_and_body(&req, &file_key, url.clone()) {
-                            Ok(value) => value,
-                            Err(e) => {
-                                println!("error occurred while parsing mime and body: {}", e);
-                                continue;
-                            }
-                        };
-
-                    // parse headers
-                    let headers = element.get(&headers_key);
-                    let headers: HeaderMap<HeaderValue> = if headers.is_none() {
-                        Default::default()
-                    } else {
-                        parse_headers(headers.unwrap())
-                    };
-
-                    println!("insert url: {}", &url);
-                    // add route
-                    ROUTES.insert(
-                        url.clone(),
-                        RouteInfo {
-                            url,
-                            method: method.clone(),
-                            status_code,
-                            mime_type,
-                            headers,
-                            body,
-                        },
-                    );
-                }
-                _ => {
-                    println!("not hash element");
-                }
+    // format is checked here and parsed again when access log is opened
+    let access_log_format = matches.value_of("access_log_format").unwrap_or("combined");
+    if let Err(e) = access_log_format.parse::<LogFormat>() {
+        println!("parse access log format failed: {}", e);
+        return Err(e.into());
+    }
+    CONFIGURATION.insert(KEY_ACCESS_LOG_FORMAT, access_log_format.to_string());
+
+    for (arg, key) in [("proxy_protocol", KEY_PROXY_PROTOCOL), ("tls_proxy_protocol", KEY_TLS_PROXY_PROTOCOL)].iter() {
+        if let Some(protocol) = matches.value_of(arg) {
+            if let Err(e) = protocol.parse::<ProxyProtocol>() {
+                println!("parse {} failed: {}", arg, e);
+                return Err(e.into());
             }
+            CONFIGURATION.insert(key, protocol.to_string());
         }
     }
-}
 
-fn parse_headers(yaml: &Yaml) -> HeaderMap {
-    let headers = match yaml {
-        Hash(headers) => headers,
-        _ => {
-            println!("header type error: {:?}", yaml);
-            return Default::default();
+    if let Some(instance_id) = matches.value_of("instance_id") {
+        CONFIGURATION.insert(KEY_INSTANCE_ID, instance_id.to_string());
+    }
+
+    for (arg, key) in [("check_config", KEY_CHECK_CONFIG), ("strict", KEY_STRICT), ("no_tui", KEY_NO_TUI)].iter() {
+        if matches.is_present(arg) {
+            CONFIGURATION.insert(key, true.to_string());
+        }
+    }
+
+    // get yaml configuration
+    let yaml = matches.value_of("yaml");
+    if yaml.is_none() {
+        return Ok(());
+    }
+    // yaml file path
+    let yaml = shellexpand::full(yaml.unwrap());
+    let yaml = match yaml {
+        Ok(yaml) => yaml.to_string(),
+        Err(e) => {
+            println!("expand yaml file path failed: {:?}", e);
+            return Err(Box::new(e));
         }
     };
 
-    let mut header_map = HeaderMap::new();
-
-    for (key, value) in headers.iter() {
-        match key {
-            yaml_rust::yaml::Yaml::String(key) => match value {
-                yaml_rust::yaml::Yaml::String(value) => {
-                    let key = match HeaderName::from_str(key.as_str()) {
-                        Ok(key) => key,
-                        Err(e) => {
-                            println!("error header name: {}", e);
-                            continue;
-                        }
-                    };
-                    let value = match HeaderValue::from_str(value.as_str()) {
-                        Ok(value) => value,
-                        Err(e) => {
-                            println!("error header value: {}", e);
-                            continue;
-                        }
-                    };
-                    header_map.insert(key, value);
-                }
-                _ => {
-                    println!("value type error: {:?}", value);
-                    continue;
-                }
-            },
-            _ => {
-                println!("key type error: {:?}", key);
-                continue;
+    println!("yaml path: {}", yaml);
+    CONFIGURATION.insert(KEY_YAML, yaml);
+
+    Ok(())
+}
+
+// init tls options from yaml, command line options take precedence
+fn init_tls_by_yaml(tls: &TlsSpec) {
+    for (value, key) in [(&tls.cert, KEY_TLS_CERT), (&tls.key, KEY_TLS_KEY)].iter() {
+        if let Some(path) = value {
+            if !CONFIGURATION.contains_key(key) {
+                CONFIGURATION.insert(key, path.clone());
             }
         }
     }
 
-    header_map
-}
-
-fn parse_mime_and_body(
-    yaml: &Yaml,
-    file_key: &yaml_rust::yaml::Yaml,
-    url: String,
-) -> Result<(MimeType, Content, StatusCode), Box<dyn std::error::Error>> {
-    let element = match yaml {
-        Hash(yaml) => yaml,
-        _ => {
-            return Err(String::from("yaml type is not hash").into());
+    if let Some(port) = tls.port {
+        if !CONFIGURATION.contains_key(KEY_TLS_PORT) {
+            CONFIGURATION.insert(KEY_TLS_PORT, port.to_string());
         }
-    };
-    // get file path and convert to body
-    let mime_type = MimeType::ApplicationOctetStream;
-
-    // file filed not found, return
-    let file = element.get(file_key);
-    if file.is_none() {
-        return Ok((
-            MimeType::TextPlain,
-            Content::Content("not found file path field".to_string()),
-            StatusCode::NOT_FOUND,
-        ));
-    };
+    }
 
-    match file.unwrap() {
-        yaml_rust::yaml::Yaml::String(path) => {
-            let path = path.to_string();
-            let full_path = shellexpand::full(&path);
-            if full_path.is_ok() {
-                let full_path = full_path.unwrap().to_string();
-                let abs_path = Path::new(&full_path);
-                // not file or no permmision to access, return
-                if !abs_path.is_file() {
-                    println!("file error: {:?}", abs_path.as_os_str());
-                    return Ok((
-                        MimeType::TextPlain,
-                        Content::Content(format!("not a file: {:?}", abs_path).to_string()),
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                    ));
-                }
-                // check file extension
-                let extension = abs_path.extension();
-                if extension.is_none() {
-                    return Ok((mime_type, Content::File(full_path), StatusCode::OK));
-                } else {
-                    match extension.unwrap().to_str() {
-                        Some(extension) => {
-                            let mime_type = MimeType::from_str(extension)
-                                .unwrap_or(MimeType::ApplicationOctetStream);
-                            if mime_type.is_text() {
-                                let ref meta = fs::metadata(abs_path);
-                                if meta.is_err() {
-                                    println!(
-                                        "get file metadata failed: {:?}",
-                                        meta.as_ref().err().unwrap()
-                                    );
-                                    return Ok((
-                                        MimeType::TextPlain,
-                                        Content::Content(
-                                            format!(
-                                                "get file metadata failed: {:?} => {:?}",
-                                                abs_path,
-                                                meta.as_ref().err().unwrap()
-                                            )
-                                            .to_string(),
-                                        ),
-                                        StatusCode::INTERNAL_SERVER_ERROR,
-                                    ));
-                                }
-                                let file_length = meta.as_ref().unwrap().len();
-                                if file_length <= MAX_FILE_CACHE_LENGTH {
-                                    let file = File::open(&full_path);
-                                    let mut buffer: Box<Vec<u8>> = Box::new(Vec::new());
-                                    let mut file = match file {
-                                        Ok(file) => file,
-                                        Err(e) => {
-                                            println!("open file failed: {:?}", e);
-                                            return Ok((
-                                                MimeType::TextPlain,
-                                                Content::Content(
-                                                    format!(
-                                                        "open file failed: {:?} => {:?}",
-                                                        abs_path, e
-                                                    )
-                                                    .to_string(),
-                                                ),
-                                                StatusCode::INTERNAL_SERVER_ERROR,
-                                            ));
-                                        }
-                                    };
-                                    match file.read_to_end(buffer.as_mut()) {
-                                        Ok(_) => {
-                                            FILE_CACHE.insert(url, Arc::new(buffer));
-                                            return Ok((mime_type, Content::Cache, StatusCode::OK));
-                                        }
-                                        Err(e) => {
-                                            println!("read file failed: {:?} => {:?}", e, abs_path);
-                                            return Ok((
-                                                MimeType::TextPlain,
-                                                Content::Content(
-                                                    format!(
-                                                        "read file failed: {:?} => {:?}",
-                                                        e, abs_path
-                                                    )
-                                                    .to_string(),
-                                                ),
-                                                StatusCode::INTERNAL_SERVER_ERROR,
-                                            ));
-                                        }
-                                    }
-                                } else {
-                                    return Ok((
-                                        mime_type,
-                                        Content::File(abs_path.to_str().unwrap().to_string()),
-                                        StatusCode::OK,
-                                    ));
-                                }
-                            } else {
-                                return Ok((
-                                    MimeType::ApplicationOctetStream,
-                                    Content::File(full_path),
-                                    StatusCode::OK,
-                                ));
-                            }
-                        }
-                        _ => Err(String::from(format!(
-                            "extension to string failed: {:?}",
-                            abs_path
-                        ))
-                        .into()),
-                    }
-                }
-            } else {
-                Err(String::from(format!("path expend failed: {:?}", path)).into())
-            }
+    if let Some(protocol) = tls.proxy_protocol {
+        if !CONFIGURATION.contains_key(KEY_TLS_PROXY_PROTOCOL) {
+            CONFIGURATION.insert(KEY_TLS_PROXY_PROTOCOL, format!("{:?}", protocol));
         }
-        _ => Err(String::from(format!("file path type error: {:?}", file)).into()),
     }
 }
 
-#[allow(dead_code)]
-fn parse_status_code(yaml: &Yaml, status_code_key: &yaml_rust::yaml::Yaml) -> StatusCode {
-    let element = match yaml {
-        Hash(yaml) => yaml,
-        _ => {
-            panic!("yaml type is not hash: {:?}", yaml);
-        }
-    };
-    element.get(status_code_key).map_or_else(
-        || StatusCode::from_u16(200).unwrap(),
-        |value| {
-            let status = match value {
-                yaml_rust::yaml::Yaml::String(code) => match StatusCode::from_str(code.as_str()) {
-                    Ok(status) => Some(status),
-                    Err(e) => {
-                        println!("parse status code failed: {}", e);
-                        None
-                    }
-                },
-                _ => {
-                    println!("unknown status code: {:?}", value);
-                    None
-                }
-            };
-            if status.is_none() {
-                println!("use default status code 200");
-                return StatusCode::from_u16(200).unwrap();
-            }
-            status.unwrap()
-        },
-    )
+// whether any listener expects proxy protocol header
+fn proxy_protocol_enabled() -> bool {
+    CONFIGURATION.contains_key(KEY_PROXY_PROTOCOL) || CONFIGURATION.contains_key(KEY_TLS_PROXY_PROTOCOL)
 }
@@ -0,0 +1,42 @@
+use std::fmt::{Display, Write};
+
+/// content type of prometheus text format
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// builder of metrics in prometheus text format
+#[derive(Default)]
+pub struct MetricsWriter {
+    buffer: String,
+}
+
+impl MetricsWriter {
+    /// start a metric family, its samples should follow
+    pub fn family(&mut self, name: &str, kind: &str, help: &str) {
+        writeln!(self.buffer, "# HELP {} {}", name, help).unwrap();
+        writeln!(self.buffer, "# TYPE {} {}", name, kind).unwrap();
+    }
+
+    pub fn sample<V: Display>(&mut self, name: &str, labels: &[(&str, &str)], value: V) {
+        self.buffer.push_str(name);
+        if !labels.is_empty() {
+            self.buffer.push('{');
+            for (index, (label, value)) in labels.iter().enumerate() {
+                if index > 0 {
+                    self.buffer.push(',');
+                }
+                write!(self.buffer, "{}=\"{}\"", label, escape(value)).unwrap();
+            }
+            self.buffer.push('}');
+        }
+        writeln!(self.buffer, " {}", value).unwrap();
+    }
+
+    pub fn finish(self) -> String {
+        self.buffer
+    }
+}
+
+// backslash, double quote and line feed are escaped in label value
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
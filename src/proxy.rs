@@ -0,0 +1,333 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+use std::str::FromStr;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
+use tokio::time::timeout;
+
+const V1_SIGNATURE: &[u8] = b"PROXY ";
+// the longest v1 header, `PROXY TCP6` with the longest addresses and ports
+const V1_MAX_LENGTH: usize = 107;
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+// signature, version and command, family, length of addresses
+const V2_HEADER_LENGTH: usize = 16;
+// header should be received in time after connection is accepted, like nginx proxy_protocol_timeout
+const HEADER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// proxy protocol header expected by a listener, like haproxy `send-proxy` and nginx `proxy_protocol on`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProxyProtocol {
+    // text header
+    V1,
+    // binary header
+    V2,
+    // v1, v2 or no header
+    Optional,
+}
+
+impl FromStr for ProxyProtocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "v1" => Ok(ProxyProtocol::V1),
+            "v2" => Ok(ProxyProtocol::V2),
+            "optional" => Ok(ProxyProtocol::Optional),
+            _ => Err(format!("unknown proxy protocol {}, v1, v2 or optional is expected", s)),
+        }
+    }
+}
+
+enum Parsed {
+    // source address if header has one, and length of header
+    Header(Option<SocketAddr>, usize),
+    // no header, only if it's optional
+    None,
+    Incomplete,
+}
+
+/// read proxy protocol header from the start of stream, source address in it is returned,
+/// along with bytes read after it, they are the start of request, it fails if header is not received in time
+pub async fn read_header<S: AsyncRead + Unpin>(stream: &mut S, protocol: ProxyProtocol) -> io::Result<(Option<SocketAddr>, Vec<u8>)> {
+    match timeout(HEADER_TIMEOUT, read_header_untimed(stream, protocol)).await {
+        Ok(result) => result,
+        Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, "proxy protocol header is not received in time")),
+    }
+}
+
+async fn read_header_untimed<S: AsyncRead + Unpin>(stream: &mut S, protocol: ProxyProtocol) -> io::Result<(Option<SocketAddr>, Vec<u8>)> {
+    let mut buffer = Vec::with_capacity(V1_MAX_LENGTH);
+    let mut chunk = [0u8; 512];
+    loop {
+        match parse(&buffer, protocol)? {
+            Parsed::Header(source, length) => return Ok((source, buffer.split_off(length))),
+            Parsed::None => return Ok((None, buffer)),
+            Parsed::Incomplete => {}
+        }
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            // closed without sending anything, like a tcp health check
+            if buffer.is_empty() {
+                return Ok((None, buffer));
+            }
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed in proxy protocol header"));
+        }
+        buffer.extend_from_slice(&chunk[..n]);
+    }
+}
+
+// whether buffer starts with signature, None if it's too short to tell
+fn starts_with(buffer: &[u8], signature: &[u8]) -> Option<bool> {
+    let length = buffer.len().min(signature.len());
+    if buffer[..length] != signature[..length] {
+        Some(false)
+    } else if length < signature.len() {
+        None
+    } else {
+        Some(true)
+    }
+}
+
+fn parse(buffer: &[u8], protocol: ProxyProtocol) -> io::Result<Parsed> {
+    let v1 = starts_with(buffer, V1_SIGNATURE);
+    let v2 = starts_with(buffer, V2_SIGNATURE);
+    match (protocol, v1, v2) {
+        (ProxyProtocol::V1, Some(true), _) | (ProxyProtocol::Optional, Some(true), _) => parse_v1(buffer),
+        (ProxyProtocol::V2, _, Some(true)) | (ProxyProtocol::Optional, _, Some(true)) => parse_v2(buffer),
+        (ProxyProtocol::V1, Some(false), _) => Err(invalid("no proxy protocol v1 header")),
+        (ProxyProtocol::V2, _, Some(false)) => Err(invalid("no proxy protocol v2 header")),
+        (ProxyProtocol::Optional, Some(false), Some(false)) => Ok(Parsed::None),
+        _ => Ok(Parsed::Incomplete),
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+// `PROXY TCP4 <source> <destination> <source port> <destination port>\r\n`, or `PROXY UNKNOWN ...\r\n`
+fn parse_v1(buffer: &[u8]) -> io::Result<Parsed> {
+    let searched = &buffer[..buffer.len().min(V1_MAX_LENGTH)];
+    let end = match searched.windows(2).position(|window| window == b"\r\n") {
+        Some(end) => end,
+        None if buffer.len() >= V1_MAX_LENGTH => return Err(invalid("proxy protocol v1 header is too long")),
+        None => return Ok(Parsed::Incomplete),
+    };
+    let line = std::str::from_utf8(&buffer[..end]).map_err(|_| invalid("proxy protocol v1 header is not text"))?;
+    let fields: Vec<&str> = line.split(' ').collect();
+    let source = match fields.get(1) {
+        Some(&"UNKNOWN") => None,
+        Some(&"TCP4") | Some(&"TCP6") if fields.len() == 6 => {
+            let ip = IpAddr::from_str(fields[2]).map_err(|_| invalid("error source address in proxy protocol v1 header"))?;
+            if ip.is_ipv4() != (fields[1] == "TCP4") {
+                return Err(invalid("source address does not match protocol in proxy protocol v1 header"));
+            }
+            let port = u16::from_str(fields[4]).map_err(|_| invalid("error source port in proxy protocol v1 header"))?;
+            Some(SocketAddr::new(ip, port))
+        }
+        _ => return Err(invalid("error proxy protocol v1 header")),
+    };
+    Ok(Parsed::Header(source, end + 2))
+}
+
+// signature, version and command, address family and transport, length and addresses, all in network byte order
+fn parse_v2(buffer: &[u8]) -> io::Result<Parsed> {
+    if buffer.len() < V2_HEADER_LENGTH {
+        return Ok(Parsed::Incomplete);
+    }
+    if buffer[12] >> 4 != 2 {
+        return Err(invalid("unknown proxy protocol v2 version"));
+    }
+    let length = V2_HEADER_LENGTH + u16::from_be_bytes([buffer[14], buffer[15]]) as usize;
+    if buffer.len() < length {
+        return Ok(Parsed::Incomplete);
+    }
+    let addresses = &buffer[V2_HEADER_LENGTH..length];
+    let source = match (buffer[12] & 0x0f, buffer[13] >> 4) {
+        // local, like a health check of proxy itself
+        (0, _) => None,
+        (1, 1) if addresses.len() >= 12 => {
+            let ip = Ipv4Addr::new(addresses[0], addresses[1], addresses[2], addresses[3]);
+            Some(SocketAddr::new(ip.into(), u16::from_be_bytes([addresses[8], addresses[9]])))
+        }
+        (1, 2) if addresses.len() >= 36 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&addresses[..16]);
+            Some(SocketAddr::new(Ipv6Addr::from(octets).into(), u16::from_be_bytes([addresses[32], addresses[33]])))
+        }
+        // unspecified or unix socket
+        (1, 0) | (1, 3) => None,
+        (1, _) => return Err(invalid("error addresses in proxy protocol v2 header")),
+        _ => return Err(invalid("unknown proxy protocol v2 command")),
+    };
+    Ok(Parsed::Header(source, length))
+}
+
+/// stream whose bytes read ahead are read again first
+pub struct PrefixedStream<S> {
+    prefix: Vec<u8>,
+    position: usize,
+    inner: S,
+}
+
+impl<S> PrefixedStream<S> {
+    pub fn new(prefix: Vec<u8>, inner: S) -> Self {
+        PrefixedStream { prefix, position: 0, inner }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for PrefixedStream<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        if self.position < self.prefix.len() {
+            let n = buf.len().min(self.prefix.len() - self.position);
+            buf[..n].copy_from_slice(&self.prefix[self.position..self.position + n]);
+            self.position += n;
+            return Poll::Ready(Ok(n));
+        }
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for PrefixedStream<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // source address and length of header, None if no header, error message if it fails
+    fn parsed(buffer: &[u8], protocol: ProxyProtocol) -> Result<Option<(Option<SocketAddr>, usize)>, String> {
+        match parse(buffer, protocol) {
+            Ok(Parsed::Header(source, length)) => Ok(Some((source, length))),
+            Ok(Parsed::None) => Ok(None),
+            Ok(Parsed::Incomplete) => Err("incomplete".to_string()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    fn v2_header(command: u8, family: u8, addresses: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend_from_slice(&[0x20 | command, family]);
+        header.extend_from_slice(&(addresses.len() as u16).to_be_bytes());
+        header.extend_from_slice(addresses);
+        header
+    }
+
+    #[test]
+    fn v1_header() {
+        let header = b"PROXY TCP4 192.0.2.1 198.51.100.2 56324 443\r\nGET /";
+        let source = "192.0.2.1:56324".parse().ok();
+        assert_eq!(parsed(header, ProxyProtocol::V1), Ok(Some((source, header.len() - 5))));
+        assert_eq!(parsed(header, ProxyProtocol::Optional), Ok(Some((source, header.len() - 5))));
+
+        let header = b"PROXY TCP6 2001:db8::1 2001:db8::2 56324 443\r\n";
+        let source = "[2001:db8::1]:56324".parse().ok();
+        assert_eq!(parsed(header, ProxyProtocol::V1), Ok(Some((source, header.len()))));
+
+        let header = b"PROXY UNKNOWN ffff::1 ffff::2 1 2\r\n";
+        assert_eq!(parsed(header, ProxyProtocol::V1), Ok(Some((None, header.len()))));
+        assert_eq!(parsed(b"PROXY UNKNOWN\r\n", ProxyProtocol::V1), Ok(Some((None, 15))));
+    }
+
+    #[test]
+    fn v1_header_of_max_length() {
+        let address = "ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff";
+        let header = format!("PROXY TCP6 {} {} 65535 65535\r\n", address, address);
+        assert!(parsed(header.as_bytes(), ProxyProtocol::V1).unwrap().is_some());
+
+        let mut header = b"PROXY UNKNOWN ".to_vec();
+        header.resize(V1_MAX_LENGTH, b'x');
+        assert_eq!(parsed(&header, ProxyProtocol::V1), Err("proxy protocol v1 header is too long".to_string()));
+        header.extend_from_slice(b"\r\n");
+        assert_eq!(parsed(&header, ProxyProtocol::V1), Err("proxy protocol v1 header is too long".to_string()));
+    }
+
+    #[test]
+    fn v1_address_of_other_family() {
+        let header = b"PROXY TCP4 2001:db8::1 2001:db8::2 56324 443\r\n";
+        assert!(parsed(header, ProxyProtocol::V1).is_err());
+        let header = b"PROXY TCP6 192.0.2.1 198.51.100.2 56324 443\r\n";
+        assert!(parsed(header, ProxyProtocol::V1).is_err());
+        assert!(parsed(b"PROXY TCP4 192.0.2.1 198.51.100.2 56324\r\n", ProxyProtocol::V1).is_err());
+        assert!(parsed(b"PROXY TCP4 192.0.2.1 198.51.100.2 port 443\r\n", ProxyProtocol::V1).is_err());
+    }
+
+    #[test]
+    fn v2_header_addresses() {
+        let addresses = [192, 0, 2, 1, 198, 51, 100, 2, 0xdc, 0x04, 0x01, 0xbb];
+        let header = v2_header(1, 0x11, &addresses);
+        let source = "192.0.2.1:56324".parse().ok();
+        assert_eq!(parsed(&header, ProxyProtocol::V2), Ok(Some((source, 28))));
+        assert_eq!(parsed(&header, ProxyProtocol::Optional), Ok(Some((source, 28))));
+
+        let mut addresses = Vec::new();
+        addresses.extend_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        addresses.extend_from_slice(&"2001:db8::2".parse::<Ipv6Addr>().unwrap().octets());
+        addresses.extend_from_slice(&[0xdc, 0x04, 0x01, 0xbb]);
+        let header = v2_header(1, 0x21, &addresses);
+        let source = "[2001:db8::1]:56324".parse().ok();
+        assert_eq!(parsed(&header, ProxyProtocol::V2), Ok(Some((source, 52))));
+
+        // local command has no address, even if proxy sends some
+        assert_eq!(parsed(&v2_header(0, 0x00, &[]), ProxyProtocol::V2), Ok(Some((None, 16))));
+        assert_eq!(parsed(&v2_header(0, 0x11, &[0; 12]), ProxyProtocol::V2), Ok(Some((None, 28))));
+        // addresses shorter than family needs
+        assert!(parsed(&v2_header(1, 0x11, &[0; 8]), ProxyProtocol::V2).is_err());
+        let mut header = v2_header(1, 0x11, &[0; 12]);
+        header[12] = 0x11;
+        assert_eq!(parsed(&header, ProxyProtocol::V2), Err("unknown proxy protocol v2 version".to_string()));
+    }
+
+    #[test]
+    fn partial_header_is_incomplete() {
+        let v1 = b"PROXY TCP4 192.0.2.1 198.51.100.2 56324 443\r\n".to_vec();
+        let v2 = v2_header(1, 0x11, &[192, 0, 2, 1, 198, 51, 100, 2, 0xdc, 0x04, 0x01, 0xbb]);
+        for (header, protocol) in [(&v1, ProxyProtocol::V1), (&v2, ProxyProtocol::V2), (&v1, ProxyProtocol::Optional), (&v2, ProxyProtocol::Optional)].iter() {
+            for length in 0..header.len() {
+                assert_eq!(parsed(&header[..length], *protocol), Err("incomplete".to_string()), "{:?} {}", protocol, length);
+            }
+            assert!(parsed(header, *protocol).unwrap().is_some());
+        }
+    }
+
+    #[test]
+    fn optional_header_falls_through_to_request() {
+        assert_eq!(parsed(b"GET / HTTP/1.1\r\n", ProxyProtocol::Optional), Ok(None));
+        assert_eq!(parsed(b"PRI * HTTP/2.0\r\n", ProxyProtocol::Optional), Ok(None));
+        // `P` may still be `PROXY` or `PRI`
+        assert_eq!(parsed(b"P", ProxyProtocol::Optional), Err("incomplete".to_string()));
+        assert_eq!(parsed(b"PRI", ProxyProtocol::Optional), Ok(None));
+        // required header is not optional
+        assert_eq!(parsed(b"GET / HTTP/1.1\r\n", ProxyProtocol::V1), Err("no proxy protocol v1 header".to_string()));
+        assert_eq!(parsed(b"PRI * HTTP/2.0\r\n", ProxyProtocol::V2), Err("no proxy protocol v2 header".to_string()));
+    }
+
+    #[tokio::test]
+    async fn read_header_keeps_bytes_after_it() {
+        let mut stream: &[u8] = b"PROXY TCP4 192.0.2.1 198.51.100.2 56324 443\r\nGET / HTTP/1.1\r\n\r\n";
+        let (source, rest) = read_header(&mut stream, ProxyProtocol::V1).await.unwrap();
+        assert_eq!(source, "192.0.2.1:56324".parse().ok());
+        assert_eq!(rest, b"GET / HTTP/1.1\r\n\r\n");
+
+        let mut stream: &[u8] = b"GET / HTTP/1.1\r\n\r\n";
+        let (source, rest) = read_header(&mut stream, ProxyProtocol::Optional).await.unwrap();
+        assert_eq!(source, None);
+        assert_eq!(rest, b"GET / HTTP/1.1\r\n\r\n");
+
+        let mut stream: &[u8] = b"PROXY TCP4 192.0.2.1";
+        assert_eq!(read_header(&mut stream, ProxyProtocol::V1).await.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
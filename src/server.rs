@@ -0,0 +1,644 @@
+use crate::access_log::{AccessLog, LogFormat};
+use crate::admin;
+use crate::handler;
+use crate::journal::{Journal, JournalEntry};
+use crate::loader;
+use crate::proxy::{self, PrefixedStream, ProxyProtocol};
+use crate::stats::{self, MethodSlots, Stats};
+use crate::tls::{self, ServerName, TlsInfo};
+use crate::types::client::{ClientAddr, PeerAddr};
+use crate::types::config::RouteConfig;
+use crate::types::diagnostic::Diagnostics;
+use crate::types::spec::{FilterSpec, RouteSpec, DEFAULT_HOST};
+use crate::types::template;
+use hyper::server::conn::Http;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Method, Server};
+use std::convert::Infallible;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+use tokio_rustls::rustls::Session;
+use tokio_rustls::TlsAcceptor;
+use yaml_rust::yaml::Hash;
+use yaml_rust::{Yaml, YamlEmitter};
+
+/// default number of the most requested unmatched paths to show
+pub const DEFAULT_TOP_UNMATCHED_PATHS: usize = 10;
+
+// keepalive of accepted tcp connections
+const TCP_KEEPALIVE: Duration = Duration::from_secs(60);
+// pause of accepting after an error
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_secs(1);
+// longest wait of shutdown for connections to finish their requests
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+type Error = Box<dyn std::error::Error + Send + Sync>;
+
+/// a route given in code, it has the keys of a route in yaml
+pub struct Route {
+    host: String,
+    method: Method,
+    yaml: Hash,
+}
+
+impl Route {
+    /// url with params or wildcard is a pattern, like in yaml
+    pub fn new(method: Method, url: &str) -> Self {
+        let route = Route { host: DEFAULT_HOST.to_string(), method, yaml: Hash::new() };
+        route.key("url", Yaml::String(url.to_string()))
+    }
+
+    /// virtual host of the route, default host if not given
+    pub fn host(mut self, host: &str) -> Self {
+        self.host = host.to_lowercase();
+        self
+    }
+
+    pub fn status_code(self, status_code: u16) -> Self {
+        self.key("status_code", Yaml::Integer(status_code as i64))
+    }
+
+    pub fn body(self, body: &str) -> Self {
+        self.key("body", Yaml::String(body.to_string()))
+    }
+
+    /// file is used if both file and body are given
+    pub fn file(self, file: &str) -> Self {
+        self.key("file", Yaml::String(file.to_string()))
+    }
+
+    /// answer the request itself in json, like `type: echo` in yaml
+    pub fn echo(self) -> Self {
+        self.key("type", Yaml::String("echo".to_string()))
+    }
+
+    /// fixed time to first byte, `key("delay", ...)` takes distributions and chunk delay like yaml
+    pub fn delay(self, delay: Duration) -> Self {
+        self.key("delay", Yaml::String(format!("{}us", delay.as_micros())))
+    }
+
+    /// body and header values are templates like `served by {{instance_id}}`
+    pub fn template(self) -> Self {
+        self.key("template", Yaml::Boolean(true))
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        let headers = self.yaml.entry(Yaml::String("headers".to_string())).or_insert_with(|| Yaml::Hash(Hash::new()));
+        if let Yaml::Hash(headers) = headers {
+            headers.insert(Yaml::String(name.to_string()), Yaml::String(value.to_string()));
+        }
+        self
+    }
+
+    pub fn content_type(self, content_type: &str) -> Self {
+        self.key("content_type", Yaml::String(content_type.to_string()))
+    }
+
+    /// any key of a route in yaml, like match_headers
+    pub fn key(mut self, key: &str, value: Yaml) -> Self {
+        self.yaml.insert(Yaml::String(key.to_string()), value);
+        self
+    }
+}
+
+/// builder of a server answering requests by routes, listening on an ephemeral port of localhost by default
+pub struct TestServer {
+    addr: SocketAddr,
+    // certificate chain and private key files
+    tls: Option<(String, String)>,
+    tls_addr: Option<SocketAddr>,
+    admin_addr: Option<SocketAddr>,
+    yaml: Option<String>,
+    routes: Vec<Route>,
+    route_config: Option<RouteConfig>,
+    strict: bool,
+    top_unmatched: usize,
+    journal: Option<usize>,
+    access_log: Option<(String, LogFormat)>,
+    proxy_protocol: Option<ProxyProtocol>,
+    tls_proxy_protocol: Option<ProxyProtocol>,
+    instance_id: Option<String>,
+}
+
+impl Default for TestServer {
+    fn default() -> Self {
+        TestServer::new()
+    }
+}
+
+impl TestServer {
+    pub fn new() -> Self {
+        TestServer {
+            addr: SocketAddr::from(([127, 0, 0, 1], 0)),
+            tls: None,
+            tls_addr: None,
+            admin_addr: None,
+            yaml: None,
+            routes: Vec::new(),
+            route_config: None,
+            strict: false,
+            top_unmatched: DEFAULT_TOP_UNMATCHED_PATHS,
+            journal: None,
+            access_log: None,
+            proxy_protocol: None,
+            tls_proxy_protocol: None,
+            instance_id: None,
+        }
+    }
+
+    /// address of routes, port 0 is an ephemeral port
+    pub fn bind(mut self, addr: SocketAddr) -> Self {
+        self.addr = addr;
+        self
+    }
+
+    /// serve routes over tls too, certificate chain and private key are pem files
+    pub fn tls(mut self, cert: &str, key: &str) -> Self {
+        self.tls = Some((cert.to_string(), key.to_string()));
+        self
+    }
+
+    /// address of tls listener, an ephemeral port of the routes ip by default
+    pub fn tls_bind(mut self, addr: SocketAddr) -> Self {
+        self.tls_addr = Some(addr);
+        self
+    }
+
+    /// read proxy protocol header before requests of routes listener, client address is taken from it
+    pub fn proxy_protocol(mut self, protocol: ProxyProtocol) -> Self {
+        self.proxy_protocol = Some(protocol);
+        self
+    }
+
+    /// read proxy protocol header before tls handshake of tls listener
+    pub fn tls_proxy_protocol(mut self, protocol: ProxyProtocol) -> Self {
+        self.tls_proxy_protocol = Some(protocol);
+        self
+    }
+
+    /// id of this server in templates, like backend-2, a random uuid if not given
+    pub fn instance_id(mut self, instance_id: &str) -> Self {
+        self.instance_id = Some(instance_id.to_string());
+        self
+    }
+
+    /// serve admin api on the address, disabled if not given
+    pub fn admin_bind(mut self, addr: SocketAddr) -> Self {
+        self.admin_addr = Some(addr);
+        self
+    }
+
+    /// routes from yaml source in the format of yaml file, tls block is used if tls is not given
+    pub fn yaml(mut self, source: &str) -> Self {
+        self.yaml = Some(source.to_string());
+        self
+    }
+
+    /// route after those of yaml, conditional routes of same url and method are matched in the order they are added
+    pub fn route(mut self, route: Route) -> Self {
+        self.routes.push(route);
+        self
+    }
+
+    /// routes built already, like by `loader::load_yaml`, yaml and routes are added to it
+    pub fn route_config(mut self, config: RouteConfig) -> Self {
+        self.route_config = Some(config);
+        self
+    }
+
+    /// refuse configuration with any warning, on start and by admin api
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// number of the most requested paths matching no route in statistics
+    pub fn top_unmatched(mut self, top_unmatched: usize) -> Self {
+        self.top_unmatched = top_unmatched;
+        self
+    }
+
+    /// record the most recent requests, capacity should not be 0
+    pub fn journal(mut self, capacity: usize) -> Self {
+        self.journal = Some(capacity);
+        self
+    }
+
+    /// append access log to file
+    pub fn access_log(mut self, path: &str, format: LogFormat) -> Self {
+        self.access_log = Some((path.to_string(), format));
+        self
+    }
+
+    /// bind listeners and serve them in background, it should be called in a tokio runtime
+    pub async fn start(mut self) -> Result<ServerHandle, Error> {
+        let config = self.build_route_config()?;
+        let tls_acceptor = match &self.tls {
+            Some((cert, key)) => Some(tls::create_acceptor(cert, key).map_err(|e| format!("init tls failed: {}", e))?),
+            None => None,
+        };
+        let journal = self.journal.map(Journal::new);
+        let access_log = match self.access_log.take() {
+            Some((path, format)) => {
+                Some(Arc::new(AccessLog::open(&path, format).map_err(|e| format!("open access log {} failed: {}", path, e))?))
+            }
+            None => None,
+        };
+
+        // listeners are bound before serving, so their ports are known to the caller
+        let listener = TcpListener::bind(self.addr).await.map_err(|e| format!("bind {} failed: {}", self.addr, e))?;
+        let addr = listener.local_addr()?;
+        let mut listeners = vec![Listener { listener, tls: None, proxy_protocol: self.proxy_protocol }];
+        let mut tls_addr = None;
+        if let Some(acceptor) = tls_acceptor {
+            let bind_addr = self.tls_addr.unwrap_or_else(|| SocketAddr::new(addr.ip(), 0));
+            let listener = TcpListener::bind(bind_addr).await.map_err(|e| format!("bind tls {} failed: {}", bind_addr, e))?;
+            tls_addr = Some(listener.local_addr()?);
+            listeners.push(Listener { listener, tls: Some(acceptor), proxy_protocol: self.tls_proxy_protocol });
+        }
+        let admin_listener = match self.admin_addr {
+            Some(admin_addr) => Some(std::net::TcpListener::bind(admin_addr).map_err(|e| format!("bind admin listener failed: {}", e))?),
+            None => None,
+        };
+        let admin_addr = match &admin_listener {
+            Some(admin_listener) => Some(admin_listener.local_addr()?),
+            None => None,
+        };
+
+        let stats = Arc::new(Stats::new());
+        give_slots(&stats, &config);
+        let state = Arc::new(ServerState {
+            routes: RwLock::new(Arc::new(config)),
+            stats,
+            unmatched_slots: MethodSlots::default(),
+            fallback_slots: MethodSlots::default(),
+            journal,
+            access_log,
+            strict: self.strict,
+            top_unmatched: self.top_unmatched,
+            instance_id: self.instance_id.take().unwrap_or_else(template::uuid),
+            listen_ports: std::iter::once(addr).chain(tls_addr).map(|addr| addr.port()).collect(),
+        });
+        let (shutdown, receiver) = watch::channel(false);
+        // every connection holds a sender, so shutdown knows when all of them are closed
+        let (connection, connections) = mpsc::channel(1);
+        let mut tasks = Vec::new();
+        for listener in listeners {
+            tasks.push(tokio::spawn(serve_listener(state.clone(), listener, receiver.clone(), connection.clone())));
+        }
+
+        // admin listener is apart from routes, so its requests are not in statistics
+        if let Some(admin_listener) = admin_listener {
+            let admin_state = state.clone();
+            let admin_service = make_service_fn(move |_conn| {
+                let state = admin_state.clone();
+                async move { Ok::<_, Infallible>(service_fn(move |req| admin::admin_response(state.clone(), req))) }
+            });
+            let admin = Server::from_tcp(admin_listener)?.serve(admin_service).with_graceful_shutdown(shutdown_requested(receiver));
+            tasks.push(tokio::spawn(async move {
+                if let Err(e) = admin.await {
+                    println!("admin listener failed: {}", e);
+                }
+            }));
+        }
+
+        Ok(ServerHandle { addr, tls_addr, admin_addr, state, shutdown, tasks, connections })
+    }
+
+    // routes of route config, yaml and routes in turn, diagnostics are printed if configuration is accepted
+    fn build_route_config(&mut self) -> Result<RouteConfig, Error> {
+        let mut config = self.route_config.take().unwrap_or_default();
+        let mut reports = Vec::new();
+        if let Some(source) = &self.yaml {
+            let (mut spec, mut diagnostics) = loader::parse_yaml("yaml", source)?;
+            if let Some(tls) = spec.tls.take() {
+                if let (None, Some(cert), Some(key)) = (&self.tls, tls.cert, tls.key) {
+                    self.tls = Some((cert, key));
+                }
+                if self.tls_addr.is_none() {
+                    self.tls_addr = tls.port.map(|port| SocketAddr::new(self.addr.ip(), port));
+                }
+                if self.tls_proxy_protocol.is_none() {
+                    self.tls_proxy_protocol = tls.proxy_protocol;
+                }
+            }
+            if self.proxy_protocol.is_none() {
+                self.proxy_protocol = spec.proxy_protocol.take();
+            }
+            // routes of yaml follow those built already
+            for host in spec.hosts.drain(..) {
+                config.hosts.entry(host).or_default();
+            }
+            for route in spec.routes.drain(..) {
+                loader::add_route(&config, route, &mut diagnostics);
+            }
+            if let Some(fallback) = spec.fallback.take() {
+                config.fallback = Some(Arc::new(loader::build_route_info(&config.file_cache, fallback, &mut diagnostics)));
+            }
+            reports.push(diagnostics);
+        }
+
+        for route in self.routes.drain(..) {
+            let yaml = Yaml::Hash(route.yaml);
+            let mut source = String::new();
+            if let Err(e) = YamlEmitter::new(&mut source).dump(&yaml) {
+                return Err(format!("emit route failed: {}", e).into());
+            }
+            let url = yaml["url"].as_str().unwrap_or_default().to_string();
+            let mut diagnostics = Diagnostics::new(&format!("route {} {}{}", route.method, route.host, url), &source);
+            if let Some(spec) = RouteSpec::from_yaml(&yaml, &route.host, route.method, &mut diagnostics) {
+                loader::add_route(&config, spec, &mut diagnostics);
+            }
+            reports.push(diagnostics);
+        }
+
+        if reports.iter().all(|diagnostics| loader::is_config_accepted(diagnostics, self.strict)) {
+            reports.iter().for_each(|diagnostics| print!("{}", diagnostics));
+            Ok(config)
+        } else {
+            Err(reports.iter().map(|diagnostics| diagnostics.to_string()).collect::<String>().into())
+        }
+    }
+}
+
+// give stats slots to routes and virtual hosts before config is used, so requests count them without lock,
+// routes added by admin api are given one on their first response
+fn give_slots(stats: &Stats, config: &RouteConfig) {
+    config.default_host.get_or_give(|| stats.host_slot(DEFAULT_HOST));
+    for host in config.hosts.iter() {
+        host.value().get_or_give(|| stats.host_slot(host.key()));
+    }
+    for routes in config.routes.iter() {
+        for route in routes.value().values().flatten() {
+            route.slot.get_or_give(|| stats.route_slot(&route.method, &route.url));
+        }
+    }
+}
+
+/// state of a started server, shared by its listeners and its handle
+pub struct ServerState {
+    // routes, virtual hosts and file cache, swapped when yaml is reloaded
+    routes: RwLock<Arc<RouteConfig>>,
+    pub(crate) stats: Arc<Stats>,
+    // slots of responses matching no route and of fallback, they are kept across reloads
+    pub(crate) unmatched_slots: MethodSlots,
+    pub(crate) fallback_slots: MethodSlots,
+    pub(crate) journal: Option<Journal>,
+    pub(crate) access_log: Option<Arc<AccessLog>>,
+    strict: bool,
+    top_unmatched: usize,
+    // id of this server in templates
+    instance_id: String,
+    // ports of route listeners, their connections are counted by tcp state
+    listen_ports: Vec<u16>,
+}
+
+impl ServerState {
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    /// routes used by new requests
+    pub fn route_config(&self) -> Arc<RouteConfig> {
+        self.routes.read().unwrap().clone()
+    }
+
+    /// swap in new routes, requests being answered keep the old ones
+    pub fn set_route_config(&self, config: RouteConfig) {
+        give_slots(&self.stats, &config);
+        *self.routes.write().unwrap() = Arc::new(config);
+    }
+
+    /// whether configuration can be used, any warning is refused in strict mode
+    pub fn is_config_accepted(&self, diagnostics: &Diagnostics) -> bool {
+        loader::is_config_accepted(diagnostics, self.strict)
+    }
+
+    pub fn instance_id(&self) -> &str {
+        &self.instance_id
+    }
+
+    /// the most requested paths matching no route, path -> count
+    pub fn top_unmatched_paths(&self) -> Vec<(String, u64)> {
+        self.stats.top_unmatched_paths(self.top_unmatched)
+    }
+
+    /// connections of route listeners by tcp state, (connecting, closing, established)
+    pub fn connection_states(&self) -> (usize, usize, usize) {
+        stats::connection_states(&self.listen_ports)
+    }
+
+    /// recorded requests matching filter, oldest first, empty if journal is not enabled
+    pub fn requests(&self, filter: &FilterSpec) -> Vec<(u64, Arc<JournalEntry>)> {
+        match &self.journal {
+            Some(journal) => journal.find(filter),
+            None => Vec::new(),
+        }
+    }
+
+    /// open access log file again, for logrotate which moved it away
+    pub fn reopen_access_log(&self) -> io::Result<()> {
+        match &self.access_log {
+            Some(access_log) => access_log.reopen(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// handle of a started server, listeners are shut down when it's dropped
+pub struct ServerHandle {
+    addr: SocketAddr,
+    tls_addr: Option<SocketAddr>,
+    admin_addr: Option<SocketAddr>,
+    state: Arc<ServerState>,
+    shutdown: watch::Sender<bool>,
+    tasks: Vec<JoinHandle<()>>,
+    // closed when all connections of routes are closed
+    connections: mpsc::Receiver<()>,
+}
+
+impl ServerHandle {
+    /// bound address of routes
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn tls_addr(&self) -> Option<SocketAddr> {
+        self.tls_addr
+    }
+
+    pub fn admin_addr(&self) -> Option<SocketAddr> {
+        self.admin_addr
+    }
+
+    /// statistics, routes, journal and access log of the server
+    pub fn state(&self) -> &Arc<ServerState> {
+        &self.state
+    }
+
+    /// stop accepting connections and wait for connections to finish their requests,
+    /// connections still open after SHUTDOWN_TIMEOUT are left to be closed by runtime
+    pub async fn shutdown(mut self) {
+        let _ = self.shutdown.broadcast(true);
+        let tasks = self.tasks;
+        let connections = &mut self.connections;
+        let finished = async move {
+            for task in tasks {
+                let _ = task.await;
+            }
+            while connections.recv().await.is_some() {}
+        };
+        if tokio::time::timeout(SHUTDOWN_TIMEOUT, finished).await.is_err() {
+            println!("connections are not finished in {:?}, shut down anyway", SHUTDOWN_TIMEOUT);
+        }
+    }
+}
+
+// resolves when handle asks to shut down or is dropped
+async fn shutdown_requested(mut receiver: watch::Receiver<bool>) {
+    while let Some(shutdown) = receiver.recv().await {
+        if shutdown {
+            return;
+        }
+    }
+}
+
+/// a listener of routes, over tls if acceptor is given
+struct Listener {
+    listener: TcpListener,
+    tls: Option<TlsAcceptor>,
+    // header read before tls handshake or http request
+    proxy_protocol: Option<ProxyProtocol>,
+}
+
+/// accept connections and serve them with routes, until handle asks to shut down
+async fn serve_listener(state: Arc<ServerState>, mut listener: Listener, shutdown: watch::Receiver<bool>, connection: mpsc::Sender<()>) {
+    let stopped = shutdown_requested(shutdown.clone());
+    tokio::pin!(stopped);
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.listener.accept() => accepted,
+            _ = &mut stopped => return,
+        };
+        let (mut stream, peer) = match accepted {
+            Ok(conn) => conn,
+            Err(e) => {
+                // like too many open files, wait a moment as hyper does
+                println!("accept connection failed: {}", e);
+                tokio::time::delay_for(ACCEPT_ERROR_DELAY).await;
+                continue;
+            }
+        };
+        let state = state.clone();
+        let acceptor = listener.tls.clone();
+        let proxy_protocol = listener.proxy_protocol;
+        let shutdown = shutdown.clone();
+        let connection = connection.clone();
+        tokio::spawn(async move {
+            // dropped when connection is closed
+            let _connection = connection;
+            // a peer may never send proxy protocol header or finish tls handshake
+            let stopped = shutdown_requested(shutdown.clone());
+            tokio::pin!(stopped);
+            if let Err(e) = stream.set_keepalive(Some(TCP_KEEPALIVE)) {
+                println!("set tcp keepalive failed: {}", e);
+            }
+            // client address is the one in proxy protocol header, if it has one
+            let (client, prefix) = match proxy_protocol {
+                Some(protocol) => match tokio::select! {
+                    header = proxy::read_header(&mut stream, protocol) => header,
+                    _ = &mut stopped => return,
+                } {
+                    Ok((source, prefix)) => (source.unwrap_or(peer), prefix),
+                    Err(e) => {
+                        state.stats.inc_proxy_protocol_error();
+                        println!("read proxy protocol header failed: {} => {}", peer, e);
+                        return;
+                    }
+                },
+                None => (peer, Vec::new()),
+            };
+            let stream = PrefixedStream::new(prefix, stream);
+            let addrs = (ClientAddr(client), PeerAddr(peer));
+
+            let acceptor = match acceptor {
+                Some(acceptor) => acceptor,
+                // http/1 and prior knowledge h2c are told apart by connection preface
+                None => {
+                    let _ = serve_connection(state, stream, false, None, addrs, shutdown).await;
+                    return;
+                }
+            };
+            let accepted = tokio::select! {
+                accepted = acceptor.accept(stream) => accepted,
+                _ = &mut stopped => return,
+            };
+            let stream = match accepted {
+                Ok(stream) => stream,
+                Err(e) => {
+                    println!("tls handshake failed: {}", e);
+                    return;
+                }
+            };
+            let tls = TlsInfo::from_session(stream.get_ref().1);
+            // h2 negotiated by alpn is served without sniffing, others may still send h2 preface
+            let h2 = stream.get_ref().1.get_alpn_protocol() == Some(tls::ALPN_H2);
+            if let Err(e) = serve_connection(state, stream, h2, Some(tls), addrs, shutdown).await {
+                println!("serve tls connection failed: {}", e);
+            }
+        });
+    }
+}
+
+/// serve requests of a connection by routes, it's closed gracefully when handle asks to shut down
+async fn serve_connection<S>(
+    state: Arc<ServerState>,
+    stream: S,
+    h2: bool,
+    tls: Option<TlsInfo>,
+    (client, peer): (ClientAddr, PeerAddr),
+    shutdown: watch::Receiver<bool>,
+) -> Result<(), hyper::Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    state.stats.inc_connections();
+    let requested = Arc::new(AtomicBool::new(false));
+    let received = requested.clone();
+    let service = service_fn(move |mut req| {
+        received.store(true, Ordering::Relaxed);
+        req.extensions_mut().insert(client);
+        req.extensions_mut().insert(peer);
+        if let Some(tls) = &tls {
+            // server name is used to select virtual host if host header does not match any
+            if let Some(server_name) = &tls.server_name {
+                req.extensions_mut().insert(ServerName(server_name.clone()));
+            }
+            req.extensions_mut().insert(tls.clone());
+        }
+        handler::response(state.clone(), req)
+    });
+    let conn = Http::new().http1_keep_alive(true).http2_only(h2).serve_connection(stream, service);
+    tokio::pin!(conn);
+    let finished = tokio::select! {
+        result = conn.as_mut() => Some(result),
+        _ = shutdown_requested(shutdown) => None,
+    };
+    // requests being answered are finished before connection is closed,
+    // a connection without any request is closed at once as hyper waits for its first one
+    match finished {
+        Some(result) => result,
+        None if !requested.load(Ordering::Relaxed) => Ok(()),
+        None => {
+            conn.as_mut().graceful_shutdown();
+            conn.await
+        }
+    }
+}
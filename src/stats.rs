@@ -0,0 +1,754 @@
+use http_body::SizeHint;
+use hyper::body::{Bytes, HttpBody};
+use hyper::header::HeaderMap;
+use hyper::{Body, Method, Version};
+use itertools::Itertools;
+use dashmap::DashMap;
+use netstat::{get_sockets_info, AddressFamilyFlags, ProtocolFlags, ProtocolSocketInfo, TcpState};
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::process;
+use std::sync::atomic::{AtomicU16, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::task::{Context, Poll};
+use std::thread;
+use std::time::Instant;
+
+// every power of two is split into this number of buckets, so error of a percentile is less than 19%
+const SUB_BUCKETS_BITS: u32 = 2;
+const SUB_BUCKETS: usize = 1 << SUB_BUCKETS_BITS;
+const BUCKETS: usize = 64 * SUB_BUCKETS;
+
+/// histogram of microseconds in buckets growing exponentially, recorded without lock
+pub struct Histogram {
+    buckets: Vec<AtomicU64>,
+    // the max value recorded since last time it's taken
+    max: AtomicU64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram::new()
+    }
+}
+
+impl Histogram {
+    pub fn new() -> Self {
+        Histogram { buckets: (0..BUCKETS).map(|_| AtomicU64::new(0)).collect(), max: AtomicU64::new(0) }
+    }
+
+    pub fn record(&self, micros: u64) {
+        self.buckets[bucket_index(micros)].fetch_add(1, Ordering::Relaxed);
+        self.max.fetch_max(micros, Ordering::Relaxed);
+    }
+
+    /// add counts of buckets from start to snapshot
+    pub fn add_to(&self, snapshot: &mut HistogramSnapshot) {
+        for (count, bucket) in snapshot.buckets.iter_mut().zip(self.buckets.iter()) {
+            *count += bucket.load(Ordering::Relaxed);
+        }
+    }
+
+    /// the max value recorded since last call
+    pub fn take_max(&self) -> u64 {
+        self.max.swap(0, Ordering::Relaxed)
+    }
+
+    pub fn reset(&self) {
+        for bucket in self.buckets.iter() {
+            bucket.store(0, Ordering::Relaxed);
+        }
+        self.max.store(0, Ordering::Relaxed);
+    }
+}
+
+// values less than SUB_BUCKETS have their own buckets, others share a bucket with values of same
+// highest bits
+fn bucket_index(value: u64) -> usize {
+    if value < SUB_BUCKETS as u64 {
+        return value as usize;
+    }
+    let exponent = 63 - value.leading_zeros();
+    let sub_bucket = (value >> (exponent - SUB_BUCKETS_BITS)) as usize & (SUB_BUCKETS - 1);
+    (exponent - SUB_BUCKETS_BITS + 1) as usize * SUB_BUCKETS + sub_bucket
+}
+
+// the max value of a bucket, the last one is bounded by u64::MAX without overflow
+fn bucket_upper_bound(index: usize) -> u64 {
+    if index < SUB_BUCKETS {
+        return index as u64;
+    }
+    let exponent = (index / SUB_BUCKETS) as u32 + SUB_BUCKETS_BITS - 1;
+    let sub_bucket = (index % SUB_BUCKETS) as u64;
+    let lower_bound = (SUB_BUCKETS as u64 + sub_bucket) << (exponent - SUB_BUCKETS_BITS);
+    lower_bound + ((1u64 << (exponent - SUB_BUCKETS_BITS)) - 1)
+}
+
+/// counts of histogram buckets at a moment
+#[derive(Clone)]
+pub struct HistogramSnapshot {
+    buckets: Vec<u64>,
+}
+
+impl HistogramSnapshot {
+    pub fn empty() -> Self {
+        HistogramSnapshot { buckets: vec![0; BUCKETS] }
+    }
+
+    /// values recorded after previous snapshot, counts reset meanwhile are taken as zero
+    pub fn since(&self, previous: &HistogramSnapshot) -> HistogramSnapshot {
+        let buckets = self.buckets.iter().zip(previous.buckets.iter()).map(|(count, previous)| count.saturating_sub(*previous)).collect();
+        HistogramSnapshot { buckets }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    /// upper bound of the bucket where the percentile is, 0 if there is no value
+    pub fn percentile(&self, percentile: f64) -> u64 {
+        let count = self.count();
+        if count == 0 {
+            return 0;
+        }
+        let rank = ((count as f64 * percentile / 100.0).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (index, bucket) in self.buckets.iter().enumerate() {
+            seen += bucket;
+            if seen >= rank {
+                return bucket_upper_bound(index);
+            }
+        }
+        bucket_upper_bound(BUCKETS - 1)
+    }
+}
+
+/// called with sent bytes and latency in microseconds when response body is done or dropped
+pub type OnComplete = Box<dyn FnOnce(u64, u64) + Send + Sync>;
+
+/// response body recording sent bytes and latency from request head when it's done or dropped
+pub struct MeteredBody {
+    inner: Body,
+    start: Instant,
+    stats: Arc<Stats>,
+    sent: u64,
+    on_complete: Option<OnComplete>,
+}
+
+impl MeteredBody {
+    pub fn new(inner: Body, start: Instant, stats: Arc<Stats>) -> Self {
+        MeteredBody { inner, start, stats, sent: 0, on_complete: None }
+    }
+
+    pub fn on_complete(mut self, on_complete: OnComplete) -> Self {
+        self.on_complete = Some(on_complete);
+        self
+    }
+}
+
+impl HttpBody for MeteredBody {
+    type Data = Bytes;
+    type Error = hyper::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Bytes, hyper::Error>>> {
+        let poll = Pin::new(&mut self.inner).poll_data(cx);
+        if let Poll::Ready(Some(Ok(data))) = &poll {
+            self.sent += data.len() as u64;
+        }
+        poll
+    }
+
+    fn poll_trailers(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<Option<HeaderMap>, hyper::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for MeteredBody {
+    fn drop(&mut self) {
+        let micros = self.start.elapsed().as_micros() as u64;
+        let sent = self.sent;
+        let shard = self.stats.shard();
+        shard.latency.record(micros);
+        shard.bytes_out.fetch_add(sent, Ordering::Relaxed);
+        if let Some(on_complete) = self.on_complete.take() {
+            on_complete(sent, micros);
+        }
+    }
+}
+
+// status code is in 100..1000
+const STATUS_CODES: usize = 1000;
+
+// http versions counted apart
+const VERSIONS: [Version; 5] = [Version::HTTP_09, Version::HTTP_10, Version::HTTP_11, Version::HTTP_2, Version::HTTP_3];
+
+// number of distinct unmatched paths to count, paths of a scanner should not eat up memory
+const MAX_UNMATCHED_PATHS: usize = 10000;
+
+// counters of slots are allocated by chunks of this many slots, at most SLOT_CHUNKS chunks
+const SLOT_CHUNK: usize = 64;
+const SLOT_CHUNKS: usize = 1024;
+// distinct status codes counted in slots of routes, more are counted in a locked map
+const STATUS_COLUMNS: usize = 16;
+
+/// slot of a route or a virtual host not given yet
+pub const NO_SLOT: usize = usize::MAX;
+
+/// index of counters of a route or a virtual host, given by stats on first count and kept by its owner,
+/// so it's counted without lock or lookup afterwards
+#[derive(Debug)]
+pub struct Slot(AtomicUsize);
+
+impl Default for Slot {
+    fn default() -> Self {
+        Slot(AtomicUsize::new(NO_SLOT))
+    }
+}
+
+impl Slot {
+    pub fn get(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// given slot, or the one given now
+    pub fn get_or_give(&self, give: impl FnOnce() -> usize) -> usize {
+        match self.get() {
+            NO_SLOT => {
+                let slot = give();
+                self.0.store(slot, Ordering::Relaxed);
+                slot
+            }
+            slot => slot,
+        }
+    }
+}
+
+// methods whose slots are kept by MethodSlots
+const METHODS: [Method; 9] = [
+    Method::GET,
+    Method::POST,
+    Method::PUT,
+    Method::DELETE,
+    Method::HEAD,
+    Method::OPTIONS,
+    Method::CONNECT,
+    Method::PATCH,
+    Method::TRACE,
+];
+
+/// slots of a route answering any method, like unmatched requests, by request method
+#[derive(Default)]
+pub struct MethodSlots([Slot; 9]);
+
+impl MethodSlots {
+    /// None for a method not standard, it's given a slot on every count
+    pub fn get(&self, method: &Method) -> Option<&Slot> {
+        METHODS.iter().position(|element| element == method).map(|index| &self.0[index])
+    }
+}
+
+/// counters of slots, a chunk is allocated when a slot in it is first counted
+struct SlotCounters {
+    chunks: Vec<OnceLock<Box<[AtomicU64]>>>,
+    // counters of a slot
+    width: usize,
+}
+
+impl SlotCounters {
+    fn new(width: usize) -> Self {
+        SlotCounters { chunks: (0..SLOT_CHUNKS).map(|_| OnceLock::new()).collect(), width }
+    }
+
+    fn inc(&self, slot: usize, column: usize) {
+        let chunk = self.chunks[slot / SLOT_CHUNK].get_or_init(|| (0..SLOT_CHUNK * self.width).map(|_| AtomicU64::new(0)).collect());
+        chunk[slot % SLOT_CHUNK * self.width + column].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn get(&self, slot: usize, column: usize) -> u64 {
+        match self.chunks[slot / SLOT_CHUNK].get() {
+            Some(chunk) => chunk[slot % SLOT_CHUNK * self.width + column].load(Ordering::Relaxed),
+            None => 0,
+        }
+    }
+
+    fn reset(&self) {
+        for chunk in self.chunks.iter().filter_map(OnceLock::get) {
+            for count in chunk.iter() {
+                count.store(0, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+// slots given to routes and virtual hosts, they are kept across resets, so owners can keep theirs
+#[derive(Default)]
+struct Slots {
+    // method => url pattern of route => slot, route is looked up without allocation
+    routes: HashMap<Method, HashMap<String, usize>>,
+    // (method, url pattern of route) of slots
+    route_keys: Vec<(Method, String)>,
+    // virtual host => slot
+    hosts: HashMap<String, usize>,
+    host_keys: Vec<String>,
+}
+
+/// counters written mostly by one thread, so increments seldom contend, none of them is locked
+struct Shard {
+    // status code => count
+    responses: Vec<AtomicU64>,
+    connections: AtomicU64,
+    // request body bytes received
+    bytes_in: AtomicU64,
+    // response body bytes sent
+    bytes_out: AtomicU64,
+    // microseconds from request head to response completion
+    latency: Histogram,
+    // slot of route => status column => count
+    routes: SlotCounters,
+    // slot of virtual host => count
+    hosts: SlotCounters,
+    // requests by http version
+    versions: Vec<AtomicU64>,
+}
+
+impl Shard {
+    fn new() -> Self {
+        Shard {
+            responses: (0..STATUS_CODES).map(|_| AtomicU64::new(0)).collect(),
+            connections: AtomicU64::new(0),
+            bytes_in: AtomicU64::new(0),
+            bytes_out: AtomicU64::new(0),
+            latency: Histogram::new(),
+            routes: SlotCounters::new(STATUS_COLUMNS),
+            hosts: SlotCounters::new(1),
+            versions: VERSIONS.iter().map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+}
+
+static NEXT_THREAD_INDEX: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    // threads take shards in turn
+    static THREAD_INDEX: usize = NEXT_THREAD_INDEX.fetch_add(1, Ordering::Relaxed);
+}
+
+/// statistics of a server from start or from last reset
+pub struct Stats {
+    // twice of cpus, so worker threads and blocking threads seldom share one
+    shards: Vec<Shard>,
+    // requests matching no route, path => count, at most MAX_UNMATCHED_PATHS paths are counted,
+    // a path counted already takes a read lock of its map shard only
+    unmatched_paths: DashMap<String, AtomicU64>,
+    // locked only to give a slot, and by readers
+    slots: Mutex<Slots>,
+    // status codes of columns of route slots, 0 if the column is not taken yet
+    status_columns: Vec<AtomicU16>,
+    // responses of routes beyond slots or status columns, (method, route, status code) => count, they are seldom
+    overflow_routes: Mutex<HashMap<(Method, String, u16), u64>>,
+    overflow_hosts: Mutex<HashMap<String, u64>>,
+    // connections closed for missing or malformed proxy protocol header, they are seldom
+    proxy_protocol_errors: AtomicU64,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Stats::new()
+    }
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        let shards = thread::available_parallelism().map(|cpus| cpus.get()).unwrap_or(1) * 2;
+        Stats {
+            shards: (0..shards).map(|_| Shard::new()).collect(),
+            unmatched_paths: DashMap::new(),
+            slots: Mutex::new(Slots::default()),
+            status_columns: (0..STATUS_COLUMNS).map(|_| AtomicU16::new(0)).collect(),
+            overflow_routes: Mutex::new(HashMap::new()),
+            overflow_hosts: Mutex::new(HashMap::new()),
+            proxy_protocol_errors: AtomicU64::new(0),
+        }
+    }
+
+    // shard of current thread, the first one is used while the thread is exiting
+    fn shard(&self) -> &Shard {
+        let index = THREAD_INDEX.try_with(|index| *index).unwrap_or(0);
+        &self.shards[index % self.shards.len()]
+    }
+
+    /// slot of method and url pattern of a route, the same one is given every time, NO_SLOT if slots run out
+    pub fn route_slot(&self, method: &Method, route: &str) -> usize {
+        let mut slots = self.slots.lock().unwrap();
+        if let Some(slot) = slots.routes.get(method).and_then(|routes| routes.get(route)) {
+            return *slot;
+        }
+        let slot = slots.route_keys.len();
+        if slot >= SLOT_CHUNK * SLOT_CHUNKS {
+            return NO_SLOT;
+        }
+        slots.route_keys.push((method.clone(), route.to_string()));
+        slots.routes.entry(method.clone()).or_default().insert(route.to_string(), slot);
+        slot
+    }
+
+    /// slot of a virtual host, the same one is given every time, NO_SLOT if slots run out
+    pub fn host_slot(&self, host: &str) -> usize {
+        let mut slots = self.slots.lock().unwrap();
+        if let Some(slot) = slots.hosts.get(host) {
+            return *slot;
+        }
+        let slot = slots.host_keys.len();
+        if slot >= SLOT_CHUNK * SLOT_CHUNKS {
+            return NO_SLOT;
+        }
+        slots.host_keys.push(host.to_string());
+        slots.hosts.insert(host.to_string(), slot);
+        slot
+    }
+
+    // column of status code in route slots, taken by the first response of it,
+    // columns are only read once they are taken, so they are not contended
+    fn status_column(&self, status_code: u16) -> Option<usize> {
+        for (column, code) in self.status_columns.iter().enumerate() {
+            let mut taken = code.load(Ordering::Relaxed);
+            if taken == 0 {
+                taken = match code.compare_exchange(0, status_code, Ordering::Relaxed, Ordering::Relaxed) {
+                    Ok(_) => return Some(column),
+                    Err(taken) => taken,
+                };
+            }
+            if taken == status_code {
+                return Some(column);
+            }
+        }
+        None
+    }
+
+    /// increase the response number by method, route and status code,
+    /// slot of the route is given on its first response and kept in it, so later ones take no lock
+    pub fn inc_response(&self, method: &Method, route: &str, slot: &Slot, status_code: u16) {
+        let shard = self.shard();
+        if let Some(count) = shard.responses.get(status_code as usize) {
+            count.fetch_add(1, Ordering::Relaxed);
+        }
+        let slot = slot.get_or_give(|| self.route_slot(method, route));
+        match (slot, self.status_column(status_code)) {
+            (NO_SLOT, _) | (_, None) => {
+                *self.overflow_routes.lock().unwrap().entry((method.clone(), route.to_string(), status_code)).or_insert(0) += 1;
+            }
+            (slot, Some(column)) => shard.routes.inc(slot, column),
+        }
+    }
+
+    /// increase the request number of virtual host, a host without slot is given one, which takes a lock
+    pub fn inc_host_request(&self, host: &str, slot: usize) {
+        let slot = match slot {
+            NO_SLOT => self.host_slot(host),
+            slot => slot,
+        };
+        match slot {
+            NO_SLOT => *self.overflow_hosts.lock().unwrap().entry(host.to_string()).or_insert(0) += 1,
+            slot => self.shard().hosts.inc(slot, 0),
+        }
+    }
+
+    /// increase the request number of http version
+    pub fn inc_version(&self, version: Version) {
+        if let Some(index) = VERSIONS.iter().position(|element| *element == version) {
+            self.shard().versions[index].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// if a new connection comming, increase the count
+    pub fn inc_connections(&self) {
+        self.shard().connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// add received request body bytes
+    pub fn add_bytes_in(&self, bytes: u64) {
+        self.shard().bytes_in.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// increase the request number of a path matching no route
+    pub fn inc_unmatched_path(&self, path: &str) {
+        if let Some(count) = self.unmatched_paths.get(path) {
+            count.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        if self.unmatched_paths.len() < MAX_UNMATCHED_PATHS {
+            self.unmatched_paths.entry(path.to_string()).or_insert_with(|| AtomicU64::new(0)).fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// increase the number of connections failed to read proxy protocol header
+    pub fn inc_proxy_protocol_error(&self) {
+        self.proxy_protocol_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// status code -> count
+    pub fn response_statistic(&self) -> HashMap<u16, u64> {
+        let mut statistic = HashMap::new();
+        for shard in self.shards.iter() {
+            for (code, count) in shard.responses.iter().enumerate() {
+                let count = count.load(Ordering::Relaxed);
+                if count > 0 {
+                    *statistic.entry(code as u16).or_insert(0) += count;
+                }
+            }
+        }
+        statistic
+    }
+
+    /// (method, route, status code) -> count, sorted by method, route and status code
+    pub fn route_statistic(&self) -> Vec<(Method, String, u16, u64)> {
+        let mut statistic = self.overflow_routes.lock().unwrap().clone();
+        let route_keys = self.slots.lock().unwrap().route_keys.clone();
+        for (column, code) in self.status_columns.iter().enumerate() {
+            let code = code.load(Ordering::Relaxed);
+            if code == 0 {
+                break;
+            }
+            for (slot, (method, route)) in route_keys.iter().enumerate() {
+                let count: u64 = self.shards.iter().map(|shard| shard.routes.get(slot, column)).sum();
+                if count > 0 {
+                    *statistic.entry((method.clone(), route.clone(), code)).or_insert(0) += count;
+                }
+            }
+        }
+        statistic
+            .into_iter()
+            .map(|((method, route, code), count)| (method, route, code, count))
+            .sorted_by(|a, b| (a.0.as_str(), &a.1, a.2).cmp(&(b.0.as_str(), &b.1, b.2)))
+            .collect()
+    }
+
+    /// host -> count, sorted by host
+    pub fn host_statistic(&self) -> Vec<(String, u64)> {
+        let mut statistic = self.overflow_hosts.lock().unwrap().clone();
+        let host_keys = self.slots.lock().unwrap().host_keys.clone();
+        for (slot, host) in host_keys.into_iter().enumerate() {
+            let count: u64 = self.shards.iter().map(|shard| shard.hosts.get(slot, 0)).sum();
+            if count > 0 {
+                *statistic.entry(host).or_insert(0) += count;
+            }
+        }
+        statistic.into_iter().sorted().collect()
+    }
+
+    /// http version -> count, versions without request are left out
+    pub fn version_statistic(&self) -> Vec<(Version, u64)> {
+        VERSIONS
+            .iter()
+            .enumerate()
+            .map(|(index, version)| (*version, self.shards.iter().map(|shard| shard.versions[index].load(Ordering::Relaxed)).sum()))
+            .filter(|(_, count)| *count > 0)
+            .collect()
+    }
+
+    /// the most requested paths matching no route, path -> count
+    pub fn top_unmatched_paths(&self, top: usize) -> Vec<(String, u64)> {
+        self.unmatched_paths
+            .iter()
+            .map(|path_statistic| (path_statistic.key().clone(), path_statistic.value().load(Ordering::Relaxed)))
+            .sorted_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)))
+            .take(top)
+            .collect()
+    }
+
+    /// connections number from start
+    pub fn total_connections(&self) -> u64 {
+        self.shards.iter().map(|shard| shard.connections.load(Ordering::Relaxed)).sum()
+    }
+
+    /// connections failed to read proxy protocol header from start
+    pub fn proxy_protocol_errors(&self) -> u64 {
+        self.proxy_protocol_errors.load(Ordering::Relaxed)
+    }
+
+    /// request body bytes received and response body bytes sent
+    pub fn total_bytes(&self) -> (u64, u64) {
+        self.shards.iter().fold((0, 0), |(bytes_in, bytes_out), shard| {
+            (bytes_in + shard.bytes_in.load(Ordering::Relaxed), bytes_out + shard.bytes_out.load(Ordering::Relaxed))
+        })
+    }
+
+    /// latency histogram from start
+    pub fn latency_snapshot(&self) -> HistogramSnapshot {
+        let mut snapshot = HistogramSnapshot::empty();
+        for shard in self.shards.iter() {
+            shard.latency.add_to(&mut snapshot);
+        }
+        snapshot
+    }
+
+    /// the max latency since last call
+    pub fn take_latency_max(&self) -> u64 {
+        self.shards.iter().map(|shard| shard.latency.take_max()).max().unwrap_or(0)
+    }
+
+    /// set all statistics to zero, requests being answered are counted after it
+    pub fn reset(&self) {
+        for shard in self.shards.iter() {
+            for count in shard.responses.iter() {
+                count.store(0, Ordering::Relaxed);
+            }
+            shard.connections.store(0, Ordering::Relaxed);
+            shard.bytes_in.store(0, Ordering::Relaxed);
+            shard.bytes_out.store(0, Ordering::Relaxed);
+            shard.latency.reset();
+            shard.routes.reset();
+            shard.hosts.reset();
+            for count in shard.versions.iter() {
+                count.store(0, Ordering::Relaxed);
+            }
+        }
+        self.unmatched_paths.clear();
+        self.overflow_routes.lock().unwrap().clear();
+        self.overflow_hosts.lock().unwrap().clear();
+        self.proxy_protocol_errors.store(0, Ordering::Relaxed);
+    }
+}
+
+/// connections of listening ports of this process by tcp state, (connecting, closing, established)
+pub fn connection_states(listen_ports: &[u16]) -> (usize, usize, usize) {
+    let af_flags = AddressFamilyFlags::IPV4 | AddressFamilyFlags::IPV6;
+    let sockets_info = match get_sockets_info(af_flags, ProtocolFlags::TCP) {
+        Ok(sockets_info) => sockets_info,
+        Err(e) => {
+            println!("Error: get sockets info failed: {:?}", e);
+            Vec::new()
+        }
+    };
+    let process_id = process::id();
+    let mut states = (0, 0, 0);
+    for socket_info in sockets_info.iter().filter(|si| si.associated_pids.contains(&process_id)) {
+        let tcp_si = match &socket_info.protocol_socket_info {
+            ProtocolSocketInfo::Tcp(tcp_si) if listen_ports.contains(&tcp_si.local_port) => tcp_si,
+            _ => continue,
+        };
+        match tcp_si.state {
+            // syn-recvd
+            TcpState::SynReceived => states.0 += 1,
+            TcpState::FinWait1 | TcpState::FinWait2 | TcpState::CloseWait | TcpState::Closing | TcpState::LastAck | TcpState::TimeWait => {
+                states.1 += 1
+            }
+            TcpState::Established => states.2 += 1,
+            _ => {}
+        }
+    }
+    states
+}
+
+/// readable duration of microseconds
+pub fn format_micros(micros: u64) -> String {
+    if micros < 1000 {
+        format!("{}us", micros)
+    } else if micros < 1_000_000 {
+        format!("{:.1}ms", micros as f64 / 1000.0)
+    } else {
+        format!("{:.2}s", micros as f64 / 1_000_000.0)
+    }
+}
+
+/// readable size of bytes
+pub fn format_bytes(bytes: f64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1}{}", value, UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket_bounds_values() {
+        let mut values = vec![0, 1, 3, 4, 5, 7, 8, 9, u64::MAX - 1, u64::MAX];
+        for k in 2..64 {
+            values.extend_from_slice(&[(1u64 << k) - 1, 1u64 << k, (1u64 << k) + 1]);
+        }
+        for value in values {
+            let index = bucket_index(value);
+            assert!(index < BUCKETS, "{}", value);
+            assert!(bucket_upper_bound(index) >= value, "{}", value);
+            // the bucket is the first one bounding the value
+            if index > 0 {
+                assert!(bucket_upper_bound(index - 1) < value, "{}", value);
+            }
+        }
+        assert_eq!(bucket_upper_bound(bucket_index(u64::MAX)), u64::MAX);
+    }
+
+    #[test]
+    fn percentile_of_uniform_values() {
+        let histogram = Histogram::new();
+        let mut snapshot = HistogramSnapshot::empty();
+        histogram.add_to(&mut snapshot);
+        assert_eq!(snapshot.percentile(50.0), 0);
+
+        for micros in 1..=100 {
+            histogram.record(micros);
+        }
+        let mut snapshot = HistogramSnapshot::empty();
+        histogram.add_to(&mut snapshot);
+        assert_eq!(snapshot.count(), 100);
+        // upper bounds of buckets 1, 48..=55, 80..=95 and 96..=111
+        assert_eq!(snapshot.percentile(0.0), 1);
+        assert_eq!(snapshot.percentile(50.0), 55);
+        assert_eq!(snapshot.percentile(90.0), 95);
+        assert_eq!(snapshot.percentile(99.0), 111);
+        assert_eq!(snapshot.percentile(100.0), 111);
+        assert_eq!(histogram.take_max(), 100);
+    }
+
+    #[test]
+    fn count_routes_and_hosts_by_slots() {
+        let stats = Stats::new();
+        let users = Slot::default();
+        stats.inc_response(&Method::GET, "/users", &users, 200);
+        // slot is given on first response and kept by its owner
+        assert_eq!(users.get(), stats.route_slot(&Method::GET, "/users"));
+        // a route built again is given the same slot
+        let reloaded = Slot::default();
+        stats.inc_response(&Method::GET, "/users", &reloaded, 200);
+        assert_eq!(reloaded.get(), users.get());
+        stats.inc_response(&Method::GET, "/users", &users, 404);
+        assert_ne!(stats.route_slot(&Method::POST, "/users"), users.get());
+
+        // status codes beyond columns are counted as well
+        for status_code in 500..500 + STATUS_COLUMNS as u16 {
+            stats.inc_response(&Method::POST, "/users", &Slot::default(), status_code);
+        }
+        let statistic = stats.route_statistic();
+        assert_eq!(statistic.len(), 2 + STATUS_COLUMNS);
+        assert!(statistic.contains(&(Method::GET, "/users".to_string(), 200, 2)));
+        assert!(statistic.contains(&(Method::GET, "/users".to_string(), 404, 1)));
+        assert!(statistic.iter().filter(|(method, ..)| method == Method::POST).all(|(.., count)| *count == 1));
+
+        stats.inc_host_request("api", stats.host_slot("api"));
+        stats.inc_host_request("api", NO_SLOT);
+        stats.inc_host_request("default", NO_SLOT);
+        assert_eq!(stats.host_statistic(), vec![("api".to_string(), 2), ("default".to_string(), 1)]);
+
+        // slots are kept across reset
+        stats.reset();
+        assert!(stats.route_statistic().is_empty());
+        assert!(stats.host_statistic().is_empty());
+        stats.inc_response(&Method::GET, "/users", &users, 200);
+        assert_eq!(stats.route_statistic(), vec![(Method::GET, "/users".to_string(), 200, 1)]);
+    }
+}
@@ -0,0 +1,73 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+
+use tokio_rustls::rustls::internal::pemfile::{certs, pkcs8_private_keys, rsa_private_keys};
+use tokio_rustls::rustls::{Certificate, NoClientAuth, PrivateKey, ServerConfig, ServerSession, Session};
+use tokio_rustls::TlsAcceptor;
+
+/// alpn protocol id of http/2
+pub const ALPN_H2: &[u8] = b"h2";
+
+/// server name indicated by client in tls handshake
+#[derive(Clone)]
+pub struct ServerName(pub String);
+
+/// parameters negotiated in tls handshake, put in request extensions by tls listener
+#[derive(Clone)]
+pub struct TlsInfo {
+    pub server_name: Option<String>,
+    // like TLSv1_3
+    pub version: Option<String>,
+    // like TLS13_AES_256_GCM_SHA384
+    pub cipher_suite: Option<String>,
+    pub alpn: Option<String>,
+}
+
+impl TlsInfo {
+    pub fn from_session(session: &ServerSession) -> Self {
+        TlsInfo {
+            server_name: session.get_sni_hostname().map(|name| name.to_lowercase()),
+            version: session.get_protocol_version().map(|version| format!("{:?}", version)),
+            cipher_suite: session.get_negotiated_ciphersuite().map(|suite| format!("{:?}", suite.suite)),
+            alpn: session.get_alpn_protocol().map(|alpn| String::from_utf8_lossy(alpn).into_owned()),
+        }
+    }
+}
+
+/// load certificate chain from a pem file
+fn load_certs(path: &str) -> Result<Vec<Certificate>, Box<dyn std::error::Error + Send + Sync>> {
+    let file = File::open(path)?;
+    let certs = certs(&mut BufReader::new(file)).map_err(|_| format!("invalid certificate file: {}", path))?;
+    if certs.is_empty() {
+        return Err(format!("no certificate found in: {}", path).into());
+    }
+    Ok(certs)
+}
+
+/// load private key from a pem file, pkcs8 first, then rsa
+fn load_private_key(path: &str) -> Result<PrivateKey, Box<dyn std::error::Error + Send + Sync>> {
+    let file = File::open(path)?;
+    let keys = pkcs8_private_keys(&mut BufReader::new(file)).map_err(|_| format!("invalid private key file: {}", path))?;
+    if let Some(key) = keys.into_iter().next() {
+        return Ok(key);
+    }
+
+    let file = File::open(path)?;
+    let keys = rsa_private_keys(&mut BufReader::new(file)).map_err(|_| format!("invalid private key file: {}", path))?;
+    match keys.into_iter().next() {
+        Some(key) => Ok(key),
+        None => Err(format!("no private key found in: {}", path).into()),
+    }
+}
+
+/// build tls acceptor from certificate and private key files
+pub fn create_acceptor(cert: &str, key: &str) -> Result<TlsAcceptor, Box<dyn std::error::Error + Send + Sync>> {
+    let certs = load_certs(cert)?;
+    let key = load_private_key(key)?;
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config.set_single_cert(certs, key)?;
+    // h2 is preferred by alpn, clients without alpn get http/1.1
+    config.set_protocols(&[ALPN_H2.to_vec(), b"http/1.1".to_vec()]);
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
@@ -0,0 +1,10 @@
+use std::net::SocketAddr;
+
+/// address of client, put in request extensions by listeners,
+/// it's the source in proxy protocol header if there is one
+#[derive(Clone, Copy)]
+pub struct ClientAddr(pub SocketAddr);
+
+/// address of tcp peer, it's the proxy if listener expects proxy protocol
+#[derive(Clone, Copy)]
+pub struct PeerAddr(pub SocketAddr);
@@ -0,0 +1,28 @@
+use crate::stats::Slot;
+use crate::types::pattern::RoutePattern;
+use crate::types::route::RouteInfo;
+use dashmap::DashMap;
+use hyper::body::Bytes;
+use hyper::Method;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// method => routes, the first matched route is used
+pub type MethodRoutes = HashMap<Method, Vec<Arc<RouteInfo>>>;
+
+/// everything built from yaml to answer requests, it's replaced as a whole when yaml is reloaded,
+/// requests keep the one they started with and the route they matched, routes may be changed in place by admin api
+#[derive(Default)]
+pub struct RouteConfig {
+    // (host, url) => method => routes
+    pub routes: DashMap<(String, String), MethodRoutes>,
+    // host => urls with params or wildcard in routes, sorted by precedence
+    pub patterns: DashMap<String, Vec<RoutePattern>>,
+    // virtual hosts configured in yaml or by admin api, except the default host, with their counters in stats
+    pub hosts: DashMap<String, Slot>,
+    pub default_host: Slot,
+    // response for request that matches no route
+    pub fallback: Option<Arc<RouteInfo>>,
+    // file path => content
+    pub file_cache: DashMap<String, Bytes>,
+}
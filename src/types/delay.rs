@@ -0,0 +1,71 @@
+use ring::rand::{SecureRandom, SystemRandom};
+use std::time::Duration;
+
+/// body is sent by chunks of this size if a delay between chunks is configured
+pub const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// distribution of a delay, samples are never negative
+#[derive(Debug, Clone)]
+pub enum Distribution {
+    Fixed(Duration),
+    // from min to max
+    Uniform(Duration, Duration),
+    // mean and standard deviation in seconds
+    Normal(f64, f64),
+    // mu and sigma of the underlying normal distribution, mu is ln of median in seconds
+    LogNormal(f64, f64),
+}
+
+/// delays of a route response, time to first byte and between body chunks
+#[derive(Debug, Clone)]
+pub struct Delay {
+    pub first_byte: Option<Distribution>,
+    pub chunk: Option<Distribution>,
+    pub chunk_size: usize,
+}
+
+impl Distribution {
+    pub fn sample(&self) -> Duration {
+        let seconds = match self {
+            Distribution::Fixed(duration) => return *duration,
+            Distribution::Uniform(min, max) => min.as_secs_f64() + (max.as_secs_f64() - min.as_secs_f64()) * random(),
+            Distribution::Normal(mean, stddev) => mean + stddev * standard_normal(),
+            Distribution::LogNormal(mu, sigma) => (mu + sigma * standard_normal()).exp(),
+        };
+        // not a number or too long for a duration is treated as no delay
+        Duration::try_from_secs_f64(seconds.max(0.0)).unwrap_or_default()
+    }
+}
+
+/// parse duration like `200ms`, `1.5s`, `500us` or `2m`, a number without unit is milliseconds
+pub fn parse_duration(text: &str) -> Result<Duration, String> {
+    let text = text.trim();
+    let split = text.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number = number.parse::<f64>().map_err(|_| format!("error duration: {}", text))?;
+    let seconds = match unit.trim() {
+        "us" => number / 1_000_000.0,
+        "" | "ms" => number / 1_000.0,
+        "s" => number,
+        "m" => number * 60.0,
+        _ => return Err(format!("unknown unit of duration {}, us, ms, s or m is expected", text)),
+    };
+    Duration::try_from_secs_f64(seconds).map_err(|e| format!("error duration {}: {}", text, e))
+}
+
+// uniform in [0, 1)
+fn random() -> f64 {
+    let mut bytes = [0u8; 8];
+    if SystemRandom::new().fill(&mut bytes).is_err() {
+        println!("generate random bytes failed");
+    }
+    // 53 bits fit in mantissa of f64
+    (u64::from_be_bytes(bytes) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+// by box-muller transform
+fn standard_normal() -> f64 {
+    let u1 = 1.0 - random();
+    let u2 = random();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+}
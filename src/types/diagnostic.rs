@@ -0,0 +1,198 @@
+use itertools::Itertools;
+use std::collections::HashMap;
+use std::fmt;
+use yaml_rust::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust::scanner::Marker;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    // configuration is used, but may not be what is expected
+    Warning,
+    // configuration, like a route, is wrong, the whole configuration is refused
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// a problem found in yaml, located by key path like `hosts.api.get[0].url`
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub path: String,
+    // line and column of the key path, starting from 1
+    pub position: Option<(usize, usize)>,
+    pub message: String,
+}
+
+/// problems found in a yaml file
+pub struct Diagnostics {
+    file: String,
+    // key path => line and column
+    positions: HashMap<String, (usize, usize)>,
+    items: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    /// positions of key paths are read from yaml source, source with syntax error has none
+    pub fn new(file: &str, source: &str) -> Self {
+        let mut receiver = PositionReceiver::default();
+        let mut parser = Parser::new(source.chars());
+        if parser.load(&mut receiver, false).is_err() {
+            receiver.positions.clear();
+        }
+        Diagnostics { file: file.to_string(), positions: receiver.positions, items: Vec::new() }
+    }
+
+    pub fn error<S: Into<String>>(&mut self, path: &str, message: S) {
+        self.push(Severity::Error, path, message.into());
+    }
+
+    pub fn warn<S: Into<String>>(&mut self, path: &str, message: S) {
+        self.push(Severity::Warning, path, message.into());
+    }
+
+    fn push(&mut self, severity: Severity, path: &str, message: String) {
+        // use position of the nearest parent if path is not in yaml, like a missing key
+        let mut key = path;
+        let position = loop {
+            if let Some(position) = self.positions.get(key) {
+                break Some(*position);
+            }
+            match key.rfind(['.', '[']) {
+                Some(end) => key = &key[..end],
+                None => break None,
+            }
+        };
+        self.items.push(Diagnostic { severity, path: path.to_string(), position, message });
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.items.iter().any(|item| item.severity == Severity::Error)
+    }
+
+    pub fn errors(&self) -> usize {
+        self.items.iter().filter(|item| item.severity == Severity::Error).count()
+    }
+
+    pub fn warnings(&self) -> usize {
+        self.items.iter().filter(|item| item.severity == Severity::Warning).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // in the order of yaml lines
+        for item in self.items.iter().sorted_by_key(|item| item.position) {
+            match item.position {
+                Some((line, col)) => write!(f, "{}:{}:{}: ", self.file, line, col)?,
+                None => write!(f, "{}: ", self.file)?,
+            }
+            if item.path.is_empty() {
+                writeln!(f, "{}: {}", item.severity, item.message)?;
+            } else {
+                writeln!(f, "{}: {}: {}", item.severity, item.path, item.message)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// key path of a yaml node
+pub fn child_path(parent: &str, key: &str) -> String {
+    if parent.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", parent, key)
+    }
+}
+
+/// key path of an array element
+pub fn index_path(parent: &str, index: usize) -> String {
+    format!("{}[{}]", parent, index)
+}
+
+enum Frame {
+    // key is None while waiting for the next key
+    Mapping { path: String, key: Option<String> },
+    Sequence { path: String, index: usize },
+}
+
+/// record position of every key path in the first document
+#[derive(Default)]
+struct PositionReceiver {
+    stack: Vec<Frame>,
+    positions: HashMap<String, (usize, usize)>,
+    documents: usize,
+}
+
+impl PositionReceiver {
+    // key path of the node just started, None if the node is a mapping key
+    fn node_path(&mut self, event: &Event, mark: &Marker) -> Option<String> {
+        let path = match self.stack.last_mut() {
+            None => String::new(),
+            Some(Frame::Mapping { path, key }) => match key.take() {
+                Some(key) => child_path(path, &key),
+                None => {
+                    // complex keys are not used by configuration
+                    let name = match event {
+                        Event::Scalar(name, ..) => name.clone(),
+                        _ => String::from("?"),
+                    };
+                    let key_path = child_path(path, &name);
+                    self.positions.entry(key_path).or_insert((mark.line(), mark.col() + 1));
+                    *key = Some(name);
+                    return None;
+                }
+            },
+            Some(Frame::Sequence { path, index }) => {
+                *index += 1;
+                index_path(path, *index - 1)
+            }
+        };
+        // position of a mapping value is the position of its key
+        self.positions.entry(path.clone()).or_insert((mark.line(), mark.col() + 1));
+        Some(path)
+    }
+}
+
+impl MarkedEventReceiver for PositionReceiver {
+    fn on_event(&mut self, event: Event, mark: Marker) {
+        if let Event::DocumentStart = event {
+            self.documents += 1;
+        }
+        if self.documents != 1 {
+            return;
+        }
+
+        match event {
+            Event::Scalar(..) | Event::Alias(_) => {
+                self.node_path(&event, &mark);
+            }
+            Event::SequenceStart(_) | Event::MappingStart(_) => match self.node_path(&event, &mark) {
+                Some(path) => {
+                    let frame = match event {
+                        Event::SequenceStart(_) => Frame::Sequence { path, index: 0 },
+                        _ => Frame::Mapping { path, key: None },
+                    };
+                    self.stack.push(frame);
+                }
+                // a container used as key, its content is not recorded
+                None => self.stack.push(Frame::Sequence { path: String::from("?"), index: 0 }),
+            },
+            Event::SequenceEnd | Event::MappingEnd => {
+                self.stack.pop();
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use jsonpath_lib::Compiled;
+use regex::Regex;
+use serde_json::Value;
+
+/// matcher of a named request value, like query argument or header
+pub enum ValueMatcher {
+    // one of values equals to it
+    Exact(String),
+    // one of values contains it
+    Contains(String),
+    // value is present or not
+    Present(bool),
+    // one of values matches regex
+    Regex(Regex),
+}
+
+impl ValueMatcher {
+    /// check all values of a name, values are empty if name is not present
+    pub fn matches<'a, I: Iterator<Item = &'a str>>(&self, mut values: I) -> bool {
+        match self {
+            ValueMatcher::Exact(expected) => values.any(|value| value == expected),
+            ValueMatcher::Contains(expected) => values.any(|value| value.contains(expected.as_str())),
+            ValueMatcher::Present(present) => values.next().is_some() == *present,
+            ValueMatcher::Regex(regex) => values.any(|value| regex.is_match(value)),
+        }
+    }
+}
+
+/// matcher of request body
+pub enum BodyMatcher {
+    // body as text is matched
+    Text(ValueMatcher),
+    // body is json, and one of values selected by json path equals to expected value
+    JsonPath(Compiled, Value),
+}
+
+impl BodyMatcher {
+    pub fn matches(&self, body: &[u8]) -> bool {
+        match self {
+            // empty body is treated as not present
+            BodyMatcher::Text(matcher) => {
+                let body = String::from_utf8_lossy(body);
+                matcher.matches(std::iter::once(body.as_ref()).filter(|body| !body.is_empty()))
+            }
+            BodyMatcher::JsonPath(path, expected) => match serde_json::from_slice::<Value>(body) {
+                Ok(json) => match path.select(&json) {
+                    Ok(values) => values.into_iter().any(|value| value == expected),
+                    Err(_) => false,
+                },
+                Err(_) => false,
+            },
+        }
+    }
+}
@@ -1,3 +1,11 @@
+pub mod client;
+pub mod config;
+pub mod delay;
+pub mod diagnostic;
+pub mod matcher;
 pub mod mime_types;
+pub mod pattern;
 pub mod route;
+pub mod spec;
+pub mod template;
 pub mod error;
\ No newline at end of file
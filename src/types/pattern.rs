@@ -0,0 +1,218 @@
+use regex::Regex;
+use std::cmp::Ordering;
+
+/// a segment of url pattern
+#[derive(Debug, Clone)]
+enum Segment {
+    // exact text, e.g. `users`
+    Literal(String),
+    // one segment, e.g. `{id}`
+    Param(String),
+    // rest of path, e.g. `*path`, unnamed `*` matches prefix only
+    Wildcard(Option<String>),
+}
+
+impl Segment {
+    // literal is more specific than param, param is more specific than wildcard
+    fn rank(&self) -> u8 {
+        match self {
+            Segment::Literal(_) => 0,
+            Segment::Param(_) => 1,
+            Segment::Wildcard(_) => 2,
+        }
+    }
+}
+
+/// values captured from url by a pattern, name => value
+#[derive(Debug, Clone, Default)]
+pub struct PathParams(pub Vec<(String, String)>);
+
+impl PathParams {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+}
+
+#[derive(Debug, Clone)]
+enum PatternKind {
+    Segments(Vec<Segment>),
+    // named groups are captured as params
+    Regex(Regex),
+}
+
+/// url pattern like `/users/{id}`, `/static/*path`, `/api/*` or a regex
+#[derive(Debug, Clone)]
+pub struct RoutePattern {
+    // key of routes, regex is prefixed by `~`
+    pub pattern: String,
+    kind: PatternKind,
+}
+
+impl RoutePattern {
+    /// parse url into pattern, return None if url has neither param nor wildcard
+    pub fn parse(url: &str) -> Result<Option<RoutePattern>, String> {
+        let parts = url.trim_start_matches('/').split('/').collect::<Vec<&str>>();
+        let mut segments = Vec::with_capacity(parts.len());
+        for (index, part) in parts.iter().enumerate() {
+            let segment = if part.starts_with('{') && part.ends_with('}') && part.len() > 2 {
+                Segment::Param(part[1..part.len() - 1].to_string())
+            } else if let Some(name) = part.strip_prefix('*') {
+                if index != parts.len() - 1 {
+                    return Err(format!("wildcard should be the last segment: {}", url));
+                }
+                if name.is_empty() {
+                    Segment::Wildcard(None)
+                } else {
+                    Segment::Wildcard(Some(name.to_string()))
+                }
+            } else if part.contains('{') || part.contains('}') {
+                return Err(format!("param should be a whole segment: {}", url));
+            } else {
+                Segment::Literal(part.to_string())
+            };
+            segments.push(segment);
+        }
+
+        if segments.iter().all(|segment| segment.rank() == 0) {
+            return Ok(None);
+        }
+        Ok(Some(RoutePattern { pattern: url.to_string(), kind: PatternKind::Segments(segments) }))
+    }
+
+    /// build pattern from regex of url
+    pub fn regex(url_regex: &str) -> Result<RoutePattern, String> {
+        let regex = Regex::new(url_regex).map_err(|e| format!("url regex error: {}", e))?;
+        Ok(RoutePattern { pattern: format!("~{}", url_regex), kind: PatternKind::Regex(regex) })
+    }
+
+    /// match url path, return captured values if matched
+    pub fn matches(&self, path: &str) -> Option<PathParams> {
+        let segments = match &self.kind {
+            PatternKind::Segments(segments) => segments,
+            PatternKind::Regex(regex) => {
+                let captures = regex.captures(path)?;
+                let params = regex
+                    .capture_names()
+                    .flatten()
+                    .filter_map(|name| captures.name(name).map(|value| (name.to_string(), value.as_str().to_string())))
+                    .collect();
+                return Some(PathParams(params));
+            }
+        };
+        let parts = path.trim_start_matches('/').split('/').collect::<Vec<&str>>();
+        let mut params = Vec::new();
+        for (index, segment) in segments.iter().enumerate() {
+            match segment {
+                Segment::Literal(literal) => {
+                    if parts.get(index) != Some(&literal.as_str()) {
+                        return None;
+                    }
+                }
+                Segment::Param(name) => match parts.get(index) {
+                    Some(part) if !part.is_empty() => params.push((name.clone(), part.to_string())),
+                    _ => return None,
+                },
+                Segment::Wildcard(name) => {
+                    if index > parts.len() {
+                        return None;
+                    }
+                    if let Some(name) = name {
+                        params.push((name.clone(), parts[index..].join("/")));
+                    }
+                    return Some(PathParams(params));
+                }
+            }
+        }
+
+        if parts.len() == segments.len() {
+            Some(PathParams(params))
+        } else {
+            None
+        }
+    }
+
+    /// compare by precedence, more specific pattern comes first, regex comes last
+    pub fn precedence(&self, other: &RoutePattern) -> Ordering {
+        match (&self.kind, &other.kind) {
+            (PatternKind::Segments(segments), PatternKind::Segments(other_segments)) => {
+                let ranks = segments.iter().map(Segment::rank);
+                let other_ranks = other_segments.iter().map(Segment::rank);
+                ranks.cmp(other_ranks)
+            }
+            (PatternKind::Segments(_), PatternKind::Regex(_)) => Ordering::Less,
+            (PatternKind::Regex(_), PatternKind::Segments(_)) => Ordering::Greater,
+            (PatternKind::Regex(_), PatternKind::Regex(_)) => Ordering::Equal,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(url: &str) -> RoutePattern {
+        match url.strip_prefix('~') {
+            Some(regex) => RoutePattern::regex(regex).unwrap(),
+            None => RoutePattern::parse(url).unwrap().unwrap(),
+        }
+    }
+
+    #[test]
+    fn literal_param_wildcard_regex_in_order() {
+        let urls = ["~^/users/.*$", "/users/*rest", "/users/{id}/{tab}", "/users/me/{tab}"];
+        // same order whatever order they are configured in
+        for start in 0..urls.len() {
+            let mut patterns = urls.iter().cycle().skip(start).take(urls.len()).map(|url| pattern(url)).collect::<Vec<RoutePattern>>();
+            patterns.sort_by(|a, b| a.precedence(b));
+            let sorted = patterns.iter().map(|pattern| pattern.pattern.as_str()).collect::<Vec<&str>>();
+            assert_eq!(sorted, ["/users/me/{tab}", "/users/{id}/{tab}", "/users/*rest", "~^/users/.*$"]);
+        }
+        // the first matched pattern is the most specific one
+        let mut patterns = urls.iter().rev().map(|url| pattern(url)).collect::<Vec<RoutePattern>>();
+        patterns.sort_by(|a, b| a.precedence(b));
+        let matched = |path: &str| patterns.iter().find(|pattern| pattern.matches(path).is_some()).map(|pattern| pattern.pattern.clone());
+        assert_eq!(matched("/users/me/posts").as_deref(), Some("/users/me/{tab}"));
+        assert_eq!(matched("/users/42/posts").as_deref(), Some("/users/{id}/{tab}"));
+        assert_eq!(matched("/users/42").as_deref(), Some("/users/*rest"));
+        assert_eq!(matched("/users").as_deref(), Some("/users/*rest"));
+        assert_eq!(matched("/user").as_deref(), None);
+        assert_eq!(pattern("/users/{id}").precedence(&pattern("/users/{name}")), Ordering::Equal);
+    }
+
+    #[test]
+    fn wildcard_matches_rest_of_path() {
+        let params = pattern("/static/*path").matches("/static/css/site.css").unwrap();
+        assert_eq!(params.get("path"), Some("css/site.css"));
+        // nothing after prefix is an empty rest
+        let params = pattern("/static/*path").matches("/static").unwrap();
+        assert_eq!(params.get("path"), Some(""));
+        assert!(pattern("/static/*").matches("/static/a/b").is_some());
+        assert!(pattern("/static/*path").matches("/statics/a").is_none());
+    }
+
+    #[test]
+    fn param_matches_one_non_empty_segment() {
+        let users = pattern("/users/{id}");
+        assert_eq!(users.matches("/users/42").unwrap().get("id"), Some("42"));
+        assert!(users.matches("/users/").is_none());
+        assert!(users.matches("/users//").is_none());
+        assert!(users.matches("/users/42/").is_none());
+        assert!(users.matches("/users/42/posts").is_none());
+        assert!(pattern("/users/{id}/posts").matches("/users//posts").is_none());
+    }
+
+    #[test]
+    fn regex_captures_named_groups() {
+        let params = pattern("~^/items/(?P<id>[0-9]+)$").matches("/items/7").unwrap();
+        assert_eq!(params.get("id"), Some("7"));
+        assert!(pattern("~^/items/(?P<id>[0-9]+)$").matches("/items/x").is_none());
+    }
+
+    #[test]
+    fn parse_refuses_broken_segments() {
+        assert!(RoutePattern::parse("/users/me").unwrap().is_none());
+        assert!(RoutePattern::parse("/c/{id").is_err());
+        assert!(RoutePattern::parse("/c/x{id}").is_err());
+        assert!(RoutePattern::parse("/static/*path/more").is_err());
+    }
+}
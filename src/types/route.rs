@@ -2,12 +2,20 @@ use hyper::header::{Iter, HeaderName, HeaderMap, HeaderValue};
 use std::str::FromStr;
 use crate::types::mime_types::MimeType;
 use crate::types::error;
+use crate::types::delay::Delay;
+use crate::types::matcher::{BodyMatcher, ValueMatcher};
+use crate::types::template::Template;
+use crate::stats::Slot;
 use hyper::{StatusCode, Method};
 
 pub enum Content {
-    Cache,
+    Cache(String),
     Content(String),
     File(String),
+    // request itself in json
+    Echo,
+    // rendered with request, from body or text file of path
+    Template(Template, Option<String>),
 }
 
 pub struct RouteInfo {
@@ -16,7 +24,44 @@ pub struct RouteInfo {
     pub status_code: StatusCode,
     pub mime_type: MimeType,
     pub headers: HeaderMap,
-    pub body: Content
+    // headers whose values are rendered with request
+    pub header_templates: Vec<(HeaderName, Template)>,
+    pub body: Content,
+    pub delay: Option<Delay>,
+    // query argument name => matcher, all of them should match
+    pub query: Vec<(String, ValueMatcher)>,
+    // lowercase header name => matcher, all of them should match
+    pub match_headers: Vec<(String, ValueMatcher)>,
+    // all of them should match
+    pub match_body: Vec<BodyMatcher>,
+    // counters of responses of the route in stats
+    pub slot: Slot,
+}
+
+/// parts of request used to select route
+pub struct RequestParts<'a> {
+    pub query: &'a [(String, String)],
+    pub headers: &'a HeaderMap,
+    // body is read only if some route needs it
+    pub body: Option<&'a [u8]>,
+}
+
+impl RequestParts<'_> {
+    /// check request by matchers of query, headers and body, return None if body is needed but not read
+    pub fn matches(&self, query: &[(String, ValueMatcher)], match_headers: &[(String, ValueMatcher)], match_body: &[BodyMatcher]) -> Option<bool> {
+        let query_matched = query.iter().all(|(name, matcher)| {
+            matcher.matches(self.query.iter().filter(|(key, _)| key == name).map(|(_, value)| value.as_str()))
+        });
+        let headers_matched = query_matched
+            && match_headers.iter().all(|(name, matcher)| {
+                matcher.matches(self.headers.get_all(name.as_str()).iter().filter_map(|value| value.to_str().ok()))
+            });
+        if !headers_matched || match_body.is_empty() {
+            return Some(headers_matched);
+        }
+
+        self.body.map(|body| match_body.iter().all(|matcher| matcher.matches(body)))
+    }
 }
 
 impl RouteInfo{
@@ -36,10 +81,21 @@ impl RouteInfo{
             status_code,
             mime_type: MimeType::ApplicationOctetStream,
             headers: HeaderMap::new(),
-            body: Content::Cache,
+            header_templates: Vec::new(),
+            body: Content::Content(String::new()),
+            delay: None,
+            query: Vec::new(),
+            match_headers: Vec::new(),
+            match_body: Vec::new(),
+            slot: Slot::default(),
         })
     }
 
+    /// check request by matchers, return None if body is needed but not read
+    pub fn matches(&self, request: &RequestParts) -> Option<bool> {
+        request.matches(&self.query, &self.match_headers, &self.match_body)
+    }
+
     #[allow(dead_code)]
     fn with_default(url: String) -> std::result::Result<Self, Box<dyn std::error::Error>> {
         RouteInfo::new(url, Method::GET.as_str().to_string(), StatusCode::OK.as_u16())
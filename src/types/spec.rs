@@ -0,0 +1,824 @@
+use crate::proxy::ProxyProtocol;
+use crate::types::delay::{parse_duration, Delay, Distribution, DEFAULT_CHUNK_SIZE};
+use crate::types::diagnostic::{child_path, index_path, Diagnostics};
+use crate::types::matcher::{BodyMatcher, ValueMatcher};
+use crate::types::pattern::RoutePattern;
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+use hyper::{Method, StatusCode};
+use jsonpath_lib::Compiled;
+use regex::Regex;
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::str::FromStr;
+use std::time::Duration;
+use yaml_rust::yaml::Yaml::{Array, Hash};
+use yaml_rust::Yaml;
+
+// yaml key of tls block, it's not a method
+const YAML_KEY_TLS: &str = "tls";
+// yaml key of fallback response, it's not a method
+const YAML_KEY_FALLBACK: &str = "fallback";
+// yaml key of virtual hosts, host => methods
+const YAML_KEY_HOSTS: &str = "hosts";
+// yaml key of proxy protocol header expected on plain listener, it's not a method
+const YAML_KEY_PROXY_PROTOCOL: &str = "proxy_protocol";
+
+// keys of a route, others are likely typos
+const ROUTE_KEYS: &[&str] = &[
+    "url",
+    "url_regex",
+    "query",
+    "match_headers",
+    "match_body",
+    "file",
+    "body",
+    "status_code",
+    "headers",
+    "content_type",
+    "type",
+    "template",
+    "delay",
+];
+
+// keys of a filter of recorded requests
+const FILTER_KEYS: &[&str] = &[
+    "method",
+    "url",
+    "url_regex",
+    "host",
+    "route",
+    "status_code",
+    "query",
+    "match_headers",
+    "match_body",
+];
+
+// keys of delay, otherwise delay is a distribution of time to first byte
+const DELAY_KEYS: &[&str] = &["first_byte", "chunk", "chunk_size"];
+
+// response types of a route, a route without type answers by file or body
+const ROUTE_TYPE_ECHO: &str = "echo";
+
+// keys of tls block
+const TLS_KEYS: &[&str] = &["port", "cert", "key", "proxy_protocol"];
+
+// key of routes and pattern of it
+type Url = (String, Option<RoutePattern>);
+// matchers of query arguments, request headers and request body
+type Matchers = (Vec<(String, ValueMatcher)>, Vec<(String, ValueMatcher)>, Vec<BodyMatcher>);
+
+/// routes not in any virtual host belong to this host
+pub const DEFAULT_HOST: &str = "default";
+
+/// tls block of yaml, command line options take precedence
+#[derive(Default)]
+pub struct TlsSpec {
+    pub port: Option<u16>,
+    // shell expanded paths
+    pub cert: Option<String>,
+    pub key: Option<String>,
+    // header expected before tls handshake
+    pub proxy_protocol: Option<ProxyProtocol>,
+}
+
+/// a route checked from yaml, its file is not loaded yet
+pub struct RouteSpec {
+    // key path in yaml, used to locate problems found while building route
+    pub path: String,
+    pub host: String,
+    pub method: Method,
+    // key of routes, regex is prefixed by `~`
+    pub url: String,
+    // url with params or wildcard, or regex of url
+    pub pattern: Option<RoutePattern>,
+    pub status_code: Option<StatusCode>,
+    // shell expanded path
+    pub file: Option<String>,
+    pub body: Option<String>,
+    // answer the request itself in json, file and body are ignored
+    pub echo: bool,
+    // body, text file and header values are templates rendered with request
+    pub template: bool,
+    pub delay: Option<Delay>,
+    pub headers: HeaderMap,
+    pub content_type: Option<HeaderValue>,
+    // query argument name => matcher
+    pub query: Vec<(String, ValueMatcher)>,
+    // lowercase header name => matcher
+    pub match_headers: Vec<(String, ValueMatcher)>,
+    pub match_body: Vec<BodyMatcher>,
+}
+
+impl RouteSpec {
+    /// route without matchers answers every request to its url and method
+    pub fn is_unconditional(&self) -> bool {
+        self.query.is_empty() && self.match_headers.is_empty() && self.match_body.is_empty()
+    }
+
+    /// a route given alone, like by admin api, it has the keys of a route in yaml
+    pub fn from_yaml(yaml: &Yaml, host: &str, method: Method, diagnostics: &mut Diagnostics) -> Option<RouteSpec> {
+        parse_route(yaml, "", host, method, true, diagnostics)
+    }
+}
+
+/// filter of recorded requests, it has the matchers of a route and values known after routing,
+/// a filter without any key matches every request
+#[derive(Default)]
+pub struct FilterSpec {
+    pub method: Option<Method>,
+    // url or regex of url, with pattern if it's not an exact url
+    pub url: Option<Url>,
+    pub host: Option<String>,
+    // url pattern of the matched route, or unmatched
+    pub route: Option<String>,
+    pub status_code: Option<StatusCode>,
+    pub query: Vec<(String, ValueMatcher)>,
+    pub match_headers: Vec<(String, ValueMatcher)>,
+    pub match_body: Vec<BodyMatcher>,
+}
+
+impl FilterSpec {
+    pub fn from_yaml(yaml: &Yaml, diagnostics: &mut Diagnostics) -> Option<FilterSpec> {
+        let empty = Hash(Default::default());
+        let yaml = match yaml {
+            Yaml::BadValue | Yaml::Null => &empty,
+            Hash(_) => yaml,
+            _ => {
+                diagnostics.error("", "filter should be hash type");
+                return None;
+            }
+        };
+        check_keys(yaml, "", FILTER_KEYS, diagnostics);
+
+        let method = match &yaml["method"] {
+            Yaml::BadValue => None,
+            Yaml::String(method) => match Method::from_str(&method.to_uppercase()) {
+                Ok(method) => Some(method),
+                Err(e) => {
+                    diagnostics.error("method", format!("method error: {}", e));
+                    return None;
+                }
+            },
+            _ => {
+                diagnostics.error("method", "method should be string");
+                return None;
+            }
+        };
+        let url = parse_url(yaml, "", diagnostics)?;
+        let host = parse_optional_string(yaml, "host", diagnostics)?.map(|host| host.to_lowercase());
+        let route = parse_optional_string(yaml, "route", diagnostics)?;
+        let status_code = parse_status_code(&yaml["status_code"], "status_code", diagnostics);
+        let (query, match_headers, match_body) = parse_matchers(yaml, "", diagnostics)?;
+        Some(FilterSpec { method, url, host, route, status_code, query, match_headers, match_body })
+    }
+}
+
+/// configuration checked from yaml, problems are reported to diagnostics,
+/// a route with error is dropped, a route with warning is kept
+#[derive(Default)]
+pub struct ConfigSpec {
+    pub tls: Option<TlsSpec>,
+    // header expected on plain listener, command line option takes precedence
+    pub proxy_protocol: Option<ProxyProtocol>,
+    // routes in yaml order
+    pub routes: Vec<RouteSpec>,
+    // virtual hosts, except the default host
+    pub hosts: Vec<String>,
+    pub fallback: Option<RouteSpec>,
+}
+
+impl ConfigSpec {
+    pub fn from_yaml(yaml: &Yaml, diagnostics: &mut Diagnostics) -> ConfigSpec {
+        let mut spec = ConfigSpec::default();
+        if let Yaml::BadValue = yaml {
+            diagnostics.error("", "yaml is empty");
+            return spec;
+        }
+        if yaml.as_hash().is_none() {
+            diagnostics.error("", "yaml should be hash type");
+            return spec;
+        }
+
+        spec.tls = parse_tls(&yaml[YAML_KEY_TLS], diagnostics);
+        spec.proxy_protocol = parse_proxy_protocol(&yaml[YAML_KEY_PROXY_PROTOCOL], YAML_KEY_PROXY_PROTOCOL, diagnostics);
+        parse_routes(yaml, DEFAULT_HOST, "", &mut spec.routes, diagnostics);
+        parse_hosts(yaml, &mut spec, diagnostics);
+
+        let fallback = &yaml[YAML_KEY_FALLBACK];
+        match fallback {
+            Hash(_) => spec.fallback = parse_route(fallback, YAML_KEY_FALLBACK, DEFAULT_HOST, Method::GET, false, diagnostics),
+            Yaml::BadValue => {}
+            _ => diagnostics.error(YAML_KEY_FALLBACK, "fallback should be hash type"),
+        }
+
+        // a route without matchers hides later ones of the same url and method
+        let mut unconditional = HashSet::new();
+        for route in spec.routes.iter() {
+            let key = (route.host.as_str(), route.method.as_str(), route.url.as_str());
+            if unconditional.contains(&key) {
+                diagnostics.warn(&route.path, "unreachable, same url and method is configured before without matchers");
+            } else if route.is_unconditional() {
+                unconditional.insert(key);
+            }
+        }
+        spec
+    }
+}
+
+// tls options, None if there is no tls block
+fn parse_tls(yaml: &Yaml, diagnostics: &mut Diagnostics) -> Option<TlsSpec> {
+    let tls = match yaml {
+        Hash(tls) => tls,
+        Yaml::BadValue => return None,
+        _ => {
+            diagnostics.error(YAML_KEY_TLS, "tls should be hash type");
+            return None;
+        }
+    };
+    check_keys(yaml, YAML_KEY_TLS, TLS_KEYS, diagnostics);
+
+    let mut spec = TlsSpec::default();
+    for (name, value) in tls.iter() {
+        let path = child_path(YAML_KEY_TLS, name.as_str().unwrap_or("?"));
+        match (name.as_str(), value) {
+            (Some("port"), Yaml::Integer(port)) => match u16::try_from(*port) {
+                Ok(port) if port > 0 => spec.port = Some(port),
+                _ => diagnostics.warn(&path, format!("port out of range: {}", port)),
+            },
+            (Some("port"), _) => diagnostics.warn(&path, "port should be integer"),
+            (Some(name @ "cert"), Yaml::String(file)) | (Some(name @ "key"), Yaml::String(file)) => {
+                match shellexpand::full(file) {
+                    Ok(file) if name == "cert" => spec.cert = Some(file.to_string()),
+                    Ok(file) => spec.key = Some(file.to_string()),
+                    Err(e) => diagnostics.error(&path, format!("expand path failed: {}", e)),
+                }
+            }
+            (Some("cert"), _) | (Some("key"), _) => diagnostics.error(&path, "path should be string"),
+            (Some("proxy_protocol"), _) => spec.proxy_protocol = parse_proxy_protocol(value, &path, diagnostics),
+            _ => {}
+        }
+    }
+    Some(spec)
+}
+
+// proxy protocol of a listener, like `v1`, None if it's not configured
+fn parse_proxy_protocol(yaml: &Yaml, path: &str, diagnostics: &mut Diagnostics) -> Option<ProxyProtocol> {
+    match yaml {
+        Yaml::String(protocol) => match ProxyProtocol::from_str(protocol) {
+            Ok(protocol) => Some(protocol),
+            Err(e) => {
+                diagnostics.error(path, e);
+                None
+            }
+        },
+        Yaml::BadValue => None,
+        _ => {
+            diagnostics.error(path, "proxy_protocol should be string");
+            None
+        }
+    }
+}
+
+// routes of virtual hosts, every host has methods like the top level
+fn parse_hosts(yaml: &Yaml, spec: &mut ConfigSpec, diagnostics: &mut Diagnostics) {
+    let hosts = match &yaml[YAML_KEY_HOSTS] {
+        Hash(hosts) => hosts,
+        Yaml::BadValue => return,
+        _ => {
+            diagnostics.error(YAML_KEY_HOSTS, "hosts should be hash type");
+            return;
+        }
+    };
+
+    for (host, routes) in hosts.iter() {
+        let (host, path) = match host.as_str() {
+            Some(name) => (name.to_lowercase(), child_path(YAML_KEY_HOSTS, name)),
+            None => {
+                diagnostics.error(YAML_KEY_HOSTS, format!("host should be string: {:?}", host));
+                continue;
+            }
+        };
+        if host != DEFAULT_HOST && !spec.hosts.contains(&host) {
+            spec.hosts.push(host.clone());
+        }
+        match routes {
+            Hash(_) => parse_routes(routes, &host, &path, &mut spec.routes, diagnostics),
+            Yaml::Null => {}
+            _ => diagnostics.error(&path, "host should be hash type"),
+        }
+    }
+}
+
+// routes by methods of a host
+fn parse_routes(yaml: &Yaml, host: &str, path: &str, routes: &mut Vec<RouteSpec>, diagnostics: &mut Diagnostics) {
+    let yaml = match yaml {
+        Hash(yaml) => yaml,
+        _ => return,
+    };
+
+    for (key, value) in yaml.iter() {
+        let name = match key.as_str() {
+            Some(name) => name,
+            None => {
+                diagnostics.error(path, format!("method should be string: {:?}", key));
+                continue;
+            }
+        };
+        let method_path = child_path(path, name);
+        // tls, fallback, hosts and proxy protocol are not methods, they are used at top level only
+        if [YAML_KEY_TLS, YAML_KEY_FALLBACK, YAML_KEY_HOSTS, YAML_KEY_PROXY_PROTOCOL].contains(&name) {
+            if !path.is_empty() {
+                diagnostics.warn(&method_path, format!("{} is ignored in a host", name));
+            }
+            continue;
+        }
+
+        let method = match Method::from_str(&name.to_uppercase()) {
+            Ok(method) => method,
+            Err(e) => {
+                diagnostics.error(&method_path, format!("method error: {}", e));
+                continue;
+            }
+        };
+        if !is_standard_method(&method) {
+            diagnostics.warn(&method_path, format!("unknown method: {}", method));
+        }
+
+        let elements = match value {
+            Array(elements) => elements,
+            _ => {
+                diagnostics.error(&method_path, "the method's elements should be an array");
+                continue;
+            }
+        };
+        for (index, element) in elements.iter().enumerate() {
+            let path = index_path(&method_path, index);
+            if let Some(route) = parse_route(element, &path, host, method.clone(), true, diagnostics) {
+                routes.push(route);
+            }
+        }
+    }
+}
+
+fn is_standard_method(method: &Method) -> bool {
+    [
+        Method::GET,
+        Method::POST,
+        Method::PUT,
+        Method::DELETE,
+        Method::HEAD,
+        Method::OPTIONS,
+        Method::CONNECT,
+        Method::PATCH,
+        Method::TRACE,
+    ]
+    .contains(method)
+}
+
+// warn keys not in the known list
+fn check_keys(yaml: &Yaml, path: &str, keys: &[&str], diagnostics: &mut Diagnostics) {
+    if let Hash(element) = yaml {
+        for key in element.keys() {
+            match key.as_str() {
+                Some(key) if keys.contains(&key) => {}
+                Some(key) => diagnostics.warn(&child_path(path, key), "unknown key"),
+                None => diagnostics.warn(path, format!("unknown key: {:?}", key)),
+            }
+        }
+    }
+}
+
+// parse response and matchers of a route, the route is dropped on error,
+// fallback has no url
+fn parse_route(
+    yaml: &Yaml,
+    path: &str,
+    host: &str,
+    method: Method,
+    has_url: bool,
+    diagnostics: &mut Diagnostics,
+) -> Option<RouteSpec> {
+    if yaml.as_hash().is_none() {
+        diagnostics.error(path, "route should be hash type");
+        return None;
+    }
+    check_keys(yaml, path, ROUTE_KEYS, diagnostics);
+
+    // get url or regex of url
+    let (url, pattern) = if has_url {
+        match parse_url(yaml, path, diagnostics)? {
+            Some(url) => url,
+            None => {
+                diagnostics.error(path, "url or url_regex is required");
+                return None;
+            }
+        }
+    } else {
+        (String::new(), None)
+    };
+
+    let (query, match_headers, match_body) = parse_matchers(yaml, path, diagnostics)?;
+
+    // file is used if both file and body are configured
+    let file = match &yaml["file"] {
+        Yaml::BadValue => None,
+        Yaml::String(file) => match shellexpand::full(file) {
+            Ok(file) => Some(file.to_string()),
+            Err(e) => {
+                diagnostics.error(&child_path(path, "file"), format!("expand path failed: {}", e));
+                return None;
+            }
+        },
+        _ => {
+            diagnostics.error(&child_path(path, "file"), "file path should be string");
+            return None;
+        }
+    };
+    let body = match &yaml["body"] {
+        Yaml::BadValue => None,
+        Yaml::String(body) => Some(body.clone()),
+        _ => {
+            diagnostics.error(&child_path(path, "body"), "body should be string");
+            return None;
+        }
+    };
+    if file.is_some() && body.is_some() {
+        diagnostics.warn(&child_path(path, "body"), "body is ignored, file is configured");
+    }
+    let echo = match &yaml["type"] {
+        Yaml::BadValue => false,
+        Yaml::String(route_type) if route_type == ROUTE_TYPE_ECHO => true,
+        Yaml::String(route_type) => {
+            diagnostics.error(&child_path(path, "type"), format!("unknown route type {}, echo is expected", route_type));
+            return None;
+        }
+        _ => {
+            diagnostics.error(&child_path(path, "type"), "type should be string");
+            return None;
+        }
+    };
+    if echo && (file.is_some() || body.is_some()) {
+        diagnostics.warn(&child_path(path, "type"), "file and body are ignored by echo");
+    }
+    let template = match &yaml["template"] {
+        Yaml::BadValue => false,
+        Yaml::Boolean(template) => *template,
+        _ => {
+            diagnostics.error(&child_path(path, "template"), "template should be boolean");
+            return None;
+        }
+    };
+    if echo && template {
+        diagnostics.warn(&child_path(path, "template"), "template is ignored by echo");
+    }
+
+    let delay = parse_delay(&yaml["delay"], &child_path(path, "delay"), diagnostics)?;
+
+    let status_code = parse_status_code(&yaml["status_code"], &child_path(path, "status_code"), diagnostics);
+    let headers = parse_headers(&yaml["headers"], &child_path(path, "headers"), diagnostics);
+
+    // content type overrides the one guessed from file extension
+    let content_type = match &yaml["content_type"] {
+        Yaml::BadValue => None,
+        Yaml::String(content_type) => match HeaderValue::from_str(content_type) {
+            Ok(content_type) => Some(content_type),
+            Err(e) => {
+                diagnostics.warn(&child_path(path, "content_type"), format!("error content type: {}", e));
+                None
+            }
+        },
+        _ => {
+            diagnostics.warn(&child_path(path, "content_type"), "content type should be string");
+            None
+        }
+    };
+
+    Some(RouteSpec {
+        path: path.to_string(),
+        host: host.to_string(),
+        method,
+        url,
+        pattern,
+        status_code,
+        file,
+        body,
+        echo,
+        template,
+        delay,
+        headers,
+        content_type,
+        query,
+        match_headers,
+        match_body,
+    })
+}
+
+// string of key, None is returned if it's not string, Some(None) if it's not configured
+fn parse_optional_string(yaml: &Yaml, key: &str, diagnostics: &mut Diagnostics) -> Option<Option<String>> {
+    match &yaml[key] {
+        Yaml::BadValue => Some(None),
+        Yaml::String(value) => Some(Some(value.clone())),
+        _ => {
+            diagnostics.error(key, format!("{} should be string", key));
+            None
+        }
+    }
+}
+
+// url or regex of url, url with params or wildcard is matched by pattern, None is returned on error,
+// Some(None) if neither is configured
+fn parse_url(yaml: &Yaml, path: &str, diagnostics: &mut Diagnostics) -> Option<Option<Url>> {
+    match (&yaml["url"], &yaml["url_regex"]) {
+        (Yaml::String(url), Yaml::BadValue) => match RoutePattern::parse(url) {
+            Ok(pattern) => Some(Some((url.clone(), pattern))),
+            Err(e) => {
+                diagnostics.error(&child_path(path, "url"), e);
+                None
+            }
+        },
+        (Yaml::BadValue, Yaml::String(url_regex)) => match RoutePattern::regex(url_regex) {
+            Ok(pattern) => Some(Some((pattern.pattern.clone(), Some(pattern)))),
+            Err(e) => {
+                diagnostics.error(&child_path(path, "url_regex"), e);
+                None
+            }
+        },
+        (Yaml::BadValue, Yaml::BadValue) => Some(None),
+        (Yaml::BadValue, _) => {
+            diagnostics.error(&child_path(path, "url_regex"), "url regex should be string");
+            None
+        }
+        (Yaml::String(_), _) => {
+            diagnostics.error(path, "url and url_regex should not be configured together");
+            None
+        }
+        _ => {
+            diagnostics.error(&child_path(path, "url"), "url should be string");
+            None
+        }
+    }
+}
+
+// matchers of query arguments, request headers and request body, None is returned on error
+fn parse_matchers(yaml: &Yaml, path: &str, diagnostics: &mut Diagnostics) -> Option<Matchers> {
+    // query arguments should be matched
+    let query = match &yaml["query"] {
+        Yaml::BadValue => Vec::new(),
+        query => match parse_value_matchers(query) {
+            Ok(query) => query,
+            Err(e) => {
+                diagnostics.error(&child_path(path, "query"), format!("query matcher error: {}", e));
+                return None;
+            }
+        },
+    };
+
+    // request headers should be matched, header name is case insensitive
+    let match_headers = match &yaml["match_headers"] {
+        Yaml::BadValue => Vec::new(),
+        match_headers => match parse_value_matchers(match_headers) {
+            Ok(match_headers) => match_headers.into_iter().map(|(name, matcher)| (name.to_lowercase(), matcher)).collect(),
+            Err(e) => {
+                diagnostics.error(&child_path(path, "match_headers"), format!("header matcher error: {}", e));
+                return None;
+            }
+        },
+    };
+
+    // request body should be matched
+    let match_body = match &yaml["match_body"] {
+        Yaml::BadValue => Vec::new(),
+        match_body => match parse_body_matchers(match_body) {
+            Ok(match_body) => match_body,
+            Err(e) => {
+                diagnostics.error(&child_path(path, "match_body"), format!("body matcher error: {}", e));
+                return None;
+            }
+        },
+    };
+    Some((query, match_headers, match_body))
+}
+
+// parse matchers of named values, name => matcher
+fn parse_value_matchers(yaml: &Yaml) -> Result<Vec<(String, ValueMatcher)>, String> {
+    let matchers = match yaml {
+        Hash(matchers) => matchers,
+        _ => return Err(format!("matchers should be hash type: {:?}", yaml)),
+    };
+
+    let mut result = Vec::new();
+    for (name, value) in matchers.iter() {
+        let name = match name.as_str() {
+            Some(name) => name.to_string(),
+            None => return Err(format!("matcher name not string: {:?}", name)),
+        };
+        result.push((name, parse_value_matcher(value)?));
+    }
+    Ok(result)
+}
+
+// plain value is exact matcher, others are like `{present: true}`, `{contains: "text"}` or `{regex: "^[0-9]+$"}`
+fn parse_value_matcher(yaml: &Yaml) -> Result<ValueMatcher, String> {
+    match yaml {
+        Yaml::String(value) | Yaml::Real(value) => Ok(ValueMatcher::Exact(value.clone())),
+        Yaml::Integer(value) => Ok(ValueMatcher::Exact(value.to_string())),
+        Yaml::Boolean(value) => Ok(ValueMatcher::Exact(value.to_string())),
+        Hash(matcher) if matcher.len() == 1 => {
+            let (kind, value) = matcher.iter().next().unwrap();
+            match (kind.as_str(), value) {
+                (Some("exact"), Yaml::String(value)) => Ok(ValueMatcher::Exact(value.clone())),
+                (Some("contains"), Yaml::String(value)) => Ok(ValueMatcher::Contains(value.clone())),
+                (Some("present"), Yaml::Boolean(present)) => Ok(ValueMatcher::Present(*present)),
+                (Some("regex"), Yaml::String(regex)) => match Regex::new(regex) {
+                    Ok(regex) => Ok(ValueMatcher::Regex(regex)),
+                    Err(e) => Err(format!("regex error: {}", e)),
+                },
+                _ => Err(format!("unknown matcher: {:?}", yaml)),
+            }
+        }
+        _ => Err(format!("unknown matcher: {:?}", yaml)),
+    }
+}
+
+// parse body matchers, a single matcher or an array of them,
+// json path matcher is like `{json_path: "$.user", equals: "admin"}`
+fn parse_body_matchers(yaml: &Yaml) -> Result<Vec<BodyMatcher>, String> {
+    let matchers = match yaml {
+        Array(matchers) => matchers.iter().collect::<Vec<&Yaml>>(),
+        _ => vec![yaml],
+    };
+
+    let mut result = Vec::new();
+    for matcher in matchers.into_iter() {
+        let json_path = match &matcher["json_path"] {
+            Yaml::String(json_path) => json_path,
+            Yaml::BadValue => {
+                result.push(BodyMatcher::Text(parse_value_matcher(matcher)?));
+                continue;
+            }
+            json_path => return Err(format!("json path not string: {:?}", json_path)),
+        };
+        let path = Compiled::compile(json_path).map_err(|e| format!("json path error: {}", e))?;
+        let expected = match &matcher["equals"] {
+            Yaml::String(value) => serde_json::Value::from(value.as_str()),
+            Yaml::Integer(value) => serde_json::Value::from(*value),
+            Yaml::Real(value) => match value.parse::<f64>() {
+                Ok(value) => serde_json::Value::from(value),
+                Err(e) => return Err(format!("equals value error: {}", e)),
+            },
+            Yaml::Boolean(value) => serde_json::Value::from(*value),
+            Yaml::Null => serde_json::Value::Null,
+            value => return Err(format!("equals value error: {:?}", value)),
+        };
+        result.push(BodyMatcher::JsonPath(path, expected));
+    }
+    Ok(result)
+}
+
+// parse response headers, a bad header is skipped
+fn parse_headers(yaml: &Yaml, path: &str, diagnostics: &mut Diagnostics) -> HeaderMap {
+    let mut header_map = HeaderMap::new();
+    let headers = match yaml {
+        Hash(headers) => headers,
+        Yaml::BadValue => return header_map,
+        _ => {
+            diagnostics.warn(path, "headers should be hash type");
+            return header_map;
+        }
+    };
+
+    for (key, value) in headers.iter() {
+        let (key, value) = match (key, value) {
+            (Yaml::String(key), Yaml::String(value)) => (key, value),
+            (Yaml::String(key), _) => {
+                diagnostics.warn(&child_path(path, key), "header value should be string");
+                continue;
+            }
+            _ => {
+                diagnostics.warn(path, format!("header name should be string: {:?}", key));
+                continue;
+            }
+        };
+        let name = match HeaderName::from_str(key) {
+            Ok(name) => name,
+            Err(e) => {
+                diagnostics.warn(&child_path(path, key), format!("error header name: {}", e));
+                continue;
+            }
+        };
+        match HeaderValue::from_str(value) {
+            Ok(value) => {
+                header_map.insert(name, value);
+            }
+            Err(e) => diagnostics.warn(&child_path(path, key), format!("error header value: {}", e)),
+        }
+    }
+
+    header_map
+}
+
+// delay of response, None is returned on error, Some(None) if it's not configured
+fn parse_delay(yaml: &Yaml, path: &str, diagnostics: &mut Diagnostics) -> Option<Option<Delay>> {
+    let is_distribution = match yaml {
+        Yaml::BadValue => return Some(None),
+        Hash(delay) => !delay.keys().any(|key| key.as_str().is_some_and(|key| DELAY_KEYS.contains(&key))),
+        _ => true,
+    };
+    // a distribution alone is the delay of first byte
+    if is_distribution {
+        let first_byte = parse_distribution(yaml, path, diagnostics)?;
+        return Some(Some(Delay { first_byte: Some(first_byte), chunk: None, chunk_size: DEFAULT_CHUNK_SIZE }));
+    }
+    check_keys(yaml, path, DELAY_KEYS, diagnostics);
+
+    let first_byte = match &yaml["first_byte"] {
+        Yaml::BadValue => None,
+        first_byte => Some(parse_distribution(first_byte, &child_path(path, "first_byte"), diagnostics)?),
+    };
+    let chunk = match &yaml["chunk"] {
+        Yaml::BadValue => None,
+        chunk => Some(parse_distribution(chunk, &child_path(path, "chunk"), diagnostics)?),
+    };
+    let chunk_size = match &yaml["chunk_size"] {
+        Yaml::BadValue => DEFAULT_CHUNK_SIZE,
+        Yaml::Integer(size) if *size > 0 => *size as usize,
+        _ => {
+            diagnostics.error(&child_path(path, "chunk_size"), "chunk_size should be a positive integer");
+            return None;
+        }
+    };
+    if chunk.is_none() && !yaml["chunk_size"].is_badvalue() {
+        diagnostics.warn(&child_path(path, "chunk_size"), "chunk_size is ignored, there is no chunk delay");
+    }
+    Some(Some(Delay { first_byte, chunk, chunk_size }))
+}
+
+// duration like `200ms`, or one of `fixed`, `uniform: [min, max]`, `normal: {mean, stddev}`
+// and `lognormal: {median, sigma}`, None is returned on error
+fn parse_distribution(yaml: &Yaml, path: &str, diagnostics: &mut Diagnostics) -> Option<Distribution> {
+    let result = match yaml {
+        Hash(distribution) if distribution.len() == 1 => {
+            let (name, value) = distribution.iter().next().unwrap();
+            match (name.as_str(), value) {
+                (Some("fixed"), value) => yaml_duration(value).map(Distribution::Fixed),
+                (Some("uniform"), Array(range)) if range.len() == 2 => match (yaml_duration(&range[0]), yaml_duration(&range[1])) {
+                    (Ok(min), Ok(max)) if min <= max => Ok(Distribution::Uniform(min, max)),
+                    (Ok(_), Ok(_)) => Err("min of uniform is greater than max".to_string()),
+                    (Err(e), _) | (_, Err(e)) => Err(e),
+                },
+                (Some("uniform"), _) => Err("uniform should be [min, max]".to_string()),
+                (Some("normal"), Hash(_)) => match (yaml_duration(&value["mean"]), yaml_duration(&value["stddev"])) {
+                    (Ok(mean), Ok(stddev)) => Ok(Distribution::Normal(mean.as_secs_f64(), stddev.as_secs_f64())),
+                    (Err(e), _) | (_, Err(e)) => Err(format!("normal should be {{mean, stddev}}, {}", e)),
+                },
+                (Some("lognormal"), Hash(_)) => match (yaml_duration(&value["median"]), yaml_float(&value["sigma"])) {
+                    (Ok(median), Some(sigma)) if median > Duration::ZERO && sigma >= 0.0 => {
+                        Ok(Distribution::LogNormal(median.as_secs_f64().ln(), sigma))
+                    }
+                    _ => Err("lognormal should be {median, sigma}, median is positive and sigma is not negative".to_string()),
+                },
+                _ => Err(format!("unknown distribution {}, fixed, uniform, normal or lognormal is expected", name.as_str().unwrap_or("?"))),
+            }
+        }
+        Hash(_) => Err("distribution should have one of fixed, uniform, normal or lognormal".to_string()),
+        duration => yaml_duration(duration).map(Distribution::Fixed),
+    };
+    match result {
+        Ok(distribution) => Some(distribution),
+        Err(e) => {
+            diagnostics.error(path, e);
+            None
+        }
+    }
+}
+
+// duration like `200ms`, a number is milliseconds
+fn yaml_duration(yaml: &Yaml) -> Result<Duration, String> {
+    match yaml {
+        Yaml::String(duration) | Yaml::Real(duration) => parse_duration(duration),
+        Yaml::Integer(millis) if *millis >= 0 => Ok(Duration::from_millis(*millis as u64)),
+        _ => Err(format!("error duration: {:?}", yaml)),
+    }
+}
+
+fn yaml_float(yaml: &Yaml) -> Option<f64> {
+    match yaml {
+        Yaml::Real(_) => yaml.as_f64(),
+        Yaml::Integer(value) => Some(*value as f64),
+        _ => None,
+    }
+}
+
+// parse status code, default status code is used if it's invalid
+fn parse_status_code(yaml: &Yaml, path: &str, diagnostics: &mut Diagnostics) -> Option<StatusCode> {
+    let status = match yaml {
+        Yaml::BadValue => return None,
+        Yaml::Integer(code) => u16::try_from(*code).ok().and_then(|code| StatusCode::from_u16(code).ok()),
+        Yaml::String(code) => StatusCode::from_str(code).ok(),
+        _ => None,
+    };
+    if status.is_none() {
+        diagnostics.warn(path, format!("invalid status code, use default status code 200: {:?}", yaml));
+    }
+    status
+}
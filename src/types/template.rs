@@ -0,0 +1,128 @@
+use crate::types::pattern::PathParams;
+use chrono::{Local, SecondsFormat};
+use hyper::header::HeaderMap;
+use hyper::Method;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+
+// variables a template may use, besides `path.<name>`, `query.<name>` and `header.<name>`
+const VARIABLES: [&str; 9] = ["method", "path", "client_ip", "client_port", "instance_id", "now", "timestamp", "timestamp_ms", "uuid"];
+const PREFIXES: [&str; 3] = ["path.", "query.", "header."];
+
+enum Segment {
+    Text(String),
+    Variable(String),
+}
+
+/// text with `{{variable}}` like `served by {{instance_id}} for {{header.x-request-id}}`,
+/// a variable without value is rendered as empty
+pub struct Template {
+    // configured text, shown by admin api
+    pub source: String,
+    segments: Vec<Segment>,
+}
+
+/// request values a template is rendered with
+pub struct TemplateContext<'a> {
+    pub method: &'a Method,
+    pub path: &'a str,
+    pub params: Option<&'a PathParams>,
+    pub query: &'a [(String, String)],
+    pub headers: &'a HeaderMap,
+    pub client: Option<SocketAddr>,
+    pub instance_id: &'a str,
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Template, String> {
+        let mut segments = Vec::new();
+        let mut rest = source;
+        while let Some(start) = rest.find("{{") {
+            let end = match rest[start + 2..].find("}}") {
+                Some(end) => start + 2 + end,
+                None => return Err(format!("template variable is not closed: {}", &rest[start..])),
+            };
+            if start > 0 {
+                segments.push(Segment::Text(rest[..start].to_string()));
+            }
+            let name = rest[start + 2..end].trim();
+            let prefixed = PREFIXES.iter().any(|prefix| name.starts_with(prefix) && name.len() > prefix.len());
+            if !prefixed && !VARIABLES.contains(&name) {
+                return Err(format!("unknown template variable {{{{{}}}}}", name));
+            }
+            // header names are case insensitive
+            let name = if name.starts_with("header.") { name.to_lowercase() } else { name.to_string() };
+            segments.push(Segment::Variable(name));
+            rest = &rest[end + 2..];
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Text(rest.to_string()));
+        }
+        Ok(Template { source: source.to_string(), segments })
+    }
+
+    /// whether text has any variable, text without variable needs no template
+    pub fn has_variables(source: &str) -> bool {
+        source.contains("{{")
+    }
+
+    pub fn render(&self, context: &TemplateContext) -> String {
+        let mut text = String::with_capacity(self.source.len());
+        for segment in self.segments.iter() {
+            match segment {
+                Segment::Text(value) => text.push_str(value),
+                Segment::Variable(name) => text.push_str(&context.variable(name)),
+            }
+        }
+        text
+    }
+}
+
+impl TemplateContext<'_> {
+    fn variable(&self, name: &str) -> String {
+        match name {
+            "method" => self.method.to_string(),
+            "path" => self.path.to_string(),
+            "client_ip" => self.client.map(|client| client.ip().to_string()).unwrap_or_default(),
+            "client_port" => self.client.map(|client| client.port().to_string()).unwrap_or_default(),
+            "instance_id" => self.instance_id.to_string(),
+            "now" => Local::now().to_rfc3339_opts(SecondsFormat::Millis, false),
+            "timestamp" => Local::now().timestamp().to_string(),
+            "timestamp_ms" => Local::now().timestamp_millis().to_string(),
+            // every occurrence is a new one
+            "uuid" => uuid(),
+            _ => {
+                if let Some(param) = name.strip_prefix("path.") {
+                    self.params.and_then(|params| params.get(param)).unwrap_or("").to_string()
+                } else if let Some(arg) = name.strip_prefix("query.") {
+                    self.query.iter().find(|(key, _)| key == arg).map(|(_, value)| value.clone()).unwrap_or_default()
+                } else {
+                    let header = &name["header.".len()..];
+                    self.headers
+                        .get(header)
+                        .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+                        .unwrap_or_default()
+                }
+            }
+        }
+    }
+}
+
+/// random uuid of version 4, like 0b5f0f2e-5d3c-4a8e-9a55-2d1c0e7f4b21
+pub fn uuid() -> String {
+    let mut bytes = [0u8; 16];
+    if SystemRandom::new().fill(&mut bytes).is_err() {
+        println!("generate random bytes failed");
+    }
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let mut uuid = String::with_capacity(36);
+    for (index, byte) in bytes.iter().enumerate() {
+        if [4, 6, 8, 10].contains(&index) {
+            uuid.push('-');
+        }
+        write!(uuid, "{:02x}", byte).unwrap();
+    }
+    uuid
+}
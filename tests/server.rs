@@ -0,0 +1,138 @@
+use hyper::{Client, Method};
+use std::time::Duration;
+use test_server::access_log::LogFormat;
+use test_server::proxy::ProxyProtocol;
+use test_server::{Route, TestServer};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use yaml_rust::YamlLoader;
+
+// well below the longest wait of shutdown
+const SHUTDOWN_LIMIT: Duration = Duration::from_secs(3);
+
+#[tokio::test]
+async fn serve_route_and_shut_down() {
+    let handle = TestServer::new().route(Route::new(Method::GET, "/hello").body("hello")).start().await.unwrap();
+    let client = Client::new();
+    let res = client.get(format!("http://{}/hello", handle.addr()).parse().unwrap()).await.unwrap();
+    assert_eq!(res.status(), 200);
+    let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+    assert_eq!(&body[..], b"hello");
+
+    let res = client.get(format!("http://{}/missing", handle.addr()).parse().unwrap()).await.unwrap();
+    assert_eq!(res.status(), 404);
+    hyper::body::to_bytes(res.into_body()).await.unwrap();
+
+    let stats = handle.state().stats();
+    assert_eq!(stats.response_statistic().get(&200), Some(&1));
+    assert_eq!(stats.response_statistic().get(&404), Some(&1));
+    assert!(stats.total_connections() >= 1);
+    // routes are counted by slots given when the server is started
+    assert_eq!(
+        stats.route_statistic(),
+        vec![(Method::GET, "/hello".to_string(), 200, 1), (Method::GET, "unmatched".to_string(), 404, 1)]
+    );
+    assert_eq!(stats.host_statistic(), vec![("default".to_string(), 2)]);
+
+    // a connection without any request does not hold shutdown
+    let _idle = TcpStream::connect(handle.addr()).await.unwrap();
+    tokio::time::delay_for(Duration::from_millis(50)).await;
+    timeout(SHUTDOWN_LIMIT, handle.shutdown()).await.expect("shutdown is not finished in time");
+}
+
+#[tokio::test]
+async fn shut_down_while_waiting_proxy_protocol_header() {
+    let handle = TestServer::new().proxy_protocol(ProxyProtocol::V1).start().await.unwrap();
+    // peer sends nothing, header is still expected
+    let _silent = TcpStream::connect(handle.addr()).await.unwrap();
+    tokio::time::delay_for(Duration::from_millis(50)).await;
+    timeout(SHUTDOWN_LIMIT, handle.shutdown()).await.expect("shutdown is not finished in time");
+}
+
+#[tokio::test]
+async fn render_template_of_any_status_code() {
+    let handle = TestServer::new()
+        .route(Route::new(Method::GET, "/error").status_code(500).template().body("error {{header.x-id}}"))
+        .start()
+        .await
+        .unwrap();
+    let req = hyper::Request::get(format!("http://{}/error", handle.addr())).header("x-id", "42").body(hyper::Body::empty()).unwrap();
+    let res = Client::new().request(req).await.unwrap();
+    assert_eq!(res.status(), 500);
+    let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+    assert_eq!(&body[..], b"error 42");
+    timeout(SHUTDOWN_LIMIT, handle.shutdown()).await.expect("shutdown is not finished in time");
+}
+
+#[tokio::test]
+async fn match_start_of_body_and_stream_the_rest() {
+    let matcher = YamlLoader::load_from_str("{contains: hello}").unwrap().remove(0);
+    let handle = TestServer::new()
+        .route(Route::new(Method::POST, "/upload").key("match_body", matcher).echo())
+        .route(Route::new(Method::POST, "/upload").body("other"))
+        .start()
+        .await
+        .unwrap();
+    let client = Client::new();
+    let url = format!("http://{}/upload", handle.addr());
+
+    // longer than the part read for matching, and chunked
+    let (mut sender, body) = hyper::Body::channel();
+    tokio::spawn(async move {
+        sender.send_data("hello".into()).await.unwrap();
+        for _ in 0..16 {
+            sender.send_data(vec![b'x'; 64 * 1024].into()).await.unwrap();
+        }
+    });
+    let res = client.request(hyper::Request::post(&url).body(body).unwrap()).await.unwrap();
+    let echo: serde_json::Value = serde_json::from_slice(&hyper::body::to_bytes(res.into_body()).await.unwrap()).unwrap();
+    assert_eq!(echo["body_length"], 5 + 16 * 64 * 1024);
+
+    let res = client.request(hyper::Request::post(&url).body("bye".into()).unwrap()).await.unwrap();
+    assert_eq!(&hyper::body::to_bytes(res.into_body()).await.unwrap()[..], b"other");
+    timeout(SHUTDOWN_LIMIT, handle.shutdown()).await.expect("shutdown is not finished in time");
+}
+
+#[tokio::test]
+async fn proxy_protocol_of_plain_listener_in_yaml() {
+    let yaml = "proxy_protocol: v1\nget:\n  - url: /client\n    template: true\n    body: '{{client_ip}}:{{client_port}}'\n";
+    let handle = TestServer::new().yaml(yaml).start().await.unwrap();
+    let mut stream = TcpStream::connect(handle.addr()).await.unwrap();
+    let request = "PROXY TCP4 192.0.2.1 198.51.100.2 56324 80\r\nGET /client HTTP/1.1\r\nhost: test\r\nconnection: close\r\n\r\n";
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(response.ends_with("192.0.2.1:56324"), "{}", response);
+    timeout(SHUTDOWN_LIMIT, handle.shutdown()).await.expect("shutdown is not finished in time");
+}
+
+#[tokio::test]
+async fn write_access_log_by_its_own_thread() {
+    let path = std::env::temp_dir().join(format!("test-server-access-{}.log", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let handle = TestServer::new()
+        .route(Route::new(Method::GET, "/logged").body("logged"))
+        .access_log(path.to_str().unwrap(), "$request_method $uri $status".parse::<LogFormat>().unwrap())
+        .start()
+        .await
+        .unwrap();
+    let client = Client::new();
+    for _ in 0..3 {
+        let res = client.get(format!("http://{}/logged", handle.addr()).parse().unwrap()).await.unwrap();
+        hyper::body::to_bytes(res.into_body()).await.unwrap();
+    }
+    timeout(SHUTDOWN_LIMIT, handle.shutdown()).await.expect("shutdown is not finished in time");
+    // lines are written once the writer thread gets them
+    let mut lines = String::new();
+    for _ in 0..100 {
+        lines = std::fs::read_to_string(&path).unwrap();
+        if lines.lines().count() == 3 {
+            break;
+        }
+        tokio::time::delay_for(Duration::from_millis(10)).await;
+    }
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(lines, "GET /logged 200\n".repeat(3));
+}